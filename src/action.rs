@@ -0,0 +1,529 @@
+use crate::state::{SelectedWindow, State};
+
+/// Something the user can do. Key presses, mouse events and commands typed
+/// after `:` are all turned into actions, which `dispatch` applies to the
+/// state.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    MoveDown,
+    MoveUp,
+    MoveLeft,
+    MoveRight,
+    HalfPageDown,
+    HalfPageUp,
+    PageDown,
+    PageUp,
+    /// Go to the first item, or the one given by the count.
+    GoToTop,
+    /// Go to the last item, or the one given by the count.
+    GoToBottom,
+    /// Open the entry, or the link of its content given by the count.
+    OpenLink,
+    NextLink,
+    PreviousLink,
+    ToggleRaw,
+    ToggleRead,
+    MarkAllRead,
+    ToggleStarred,
+    ToggleStarredView,
+    ToggleSidebar,
+    CycleSort,
+    CycleGrouping,
+    Filter,
+    Find,
+    NextMatch,
+    PreviousMatch,
+    Search,
+    EditNote,
+    Highlight,
+    Cancel,
+    ToggleHelp,
+    CommandLine,
+    Refresh,
+    Quit,
+    /// A digit of the count of the next action.
+    Count(u32),
+    /// Type a character into the open prompt.
+    Type(char),
+    /// Delete the last character of the open prompt.
+    DeleteChar,
+    /// Accept the open prompt.
+    Submit,
+    /// Press the mouse button at a position, which starts dragging the
+    /// divider there if there is one, and otherwise focuses the pane under
+    /// the pointer and selects the item clicked.
+    Click {
+        column: u16,
+        row: u16,
+        width: u16,
+    },
+    /// Turn the mouse wheel over a position, by steps down or up if
+    /// negative.
+    Scroll {
+        column: u16,
+        row: u16,
+        delta: isize,
+    },
+    DragDivider {
+        column: u16,
+        width: u16,
+    },
+    ReleaseDivider,
+}
+
+/// Every action, with the name it is bound by in the keymap file and a
+/// description for the help popup, in the order they are listed there.
+/// Consecutive actions with the same description share a line.
+const ACTIONS: &[(Action, &str, &str)] = &[
+    (Action::MoveUp, "move-up", "navigate"),
+    (Action::MoveDown, "move-down", "navigate"),
+    (Action::MoveLeft, "move-left", "switch panes"),
+    (Action::MoveRight, "move-right", "switch panes"),
+    (Action::HalfPageDown, "half-page-down", "scroll half a page"),
+    (Action::HalfPageUp, "half-page-up", "scroll half a page"),
+    (Action::PageDown, "page-down", "scroll a page"),
+    (Action::PageUp, "page-up", "scroll a page"),
+    (Action::GoToTop, "go-to-top", "go to the top/bottom"),
+    (Action::GoToBottom, "go-to-bottom", "go to the top/bottom"),
+    (
+        Action::OpenLink,
+        "open-link",
+        "open the article, or link n, in the browser",
+    ),
+    (Action::NextLink, "next-link", "cycle through links"),
+    (Action::PreviousLink, "previous-link", "cycle through links"),
+    (Action::ToggleRaw, "toggle-raw", "toggle raw/html view"),
+    (Action::ToggleRead, "toggle-read", "toggle read/unread"),
+    (Action::MarkAllRead, "mark-all-read", "mark all as read"),
+    (Action::ToggleStarred, "toggle-starred", "star/unstar"),
+    (
+        Action::ToggleStarredView,
+        "toggle-starred-view",
+        "show starred entries",
+    ),
+    (
+        Action::ToggleSidebar,
+        "toggle-sidebar",
+        "show feeds and tags",
+    ),
+    (Action::CycleSort, "cycle-sort", "change the sort order"),
+    (
+        Action::CycleGrouping,
+        "cycle-grouping",
+        "change the grouping",
+    ),
+    (Action::Filter, "filter", "filter entries"),
+    (Action::Find, "find", "find text in the entry"),
+    (Action::NextMatch, "next-match", "jump to the next match"),
+    (
+        Action::PreviousMatch,
+        "previous-match",
+        "jump to the previous match",
+    ),
+    (Action::Search, "search", "search all entries"),
+    (Action::EditNote, "edit-note", "edit the entry's note"),
+    (Action::Highlight, "highlight", "highlight lines"),
+    (
+        Action::Cancel,
+        "cancel",
+        "clear the filter, find or picked link",
+    ),
+    (Action::ToggleHelp, "toggle-help", "show/hide controls"),
+    (Action::CommandLine, "command-line", "run a command"),
+    (Action::Refresh, "refresh", "refresh the feeds"),
+    (Action::Quit, "quit", "quit"),
+];
+
+impl Action {
+    /// Every action which can be bound to keys, in the order they are listed
+    /// in the help popup.
+    pub fn all() -> impl Iterator<Item = Action> {
+        ACTIONS.iter().map(|(action, _, _)| *action)
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        ACTIONS
+            .iter()
+            .find(|(_, n, _)| *n == name)
+            .map(|(action, _, _)| *action)
+    }
+
+    pub fn description(self) -> &'static str {
+        ACTIONS
+            .iter()
+            .find(|(action, _, _)| *action == self)
+            .map_or("", |(_, _, description)| description)
+    }
+}
+
+/// Run a command typed after `:`, which is the name of an action.
+fn run_command(state: &mut State, command: &str) {
+    let command = command.trim();
+    if command.is_empty() {
+        return;
+    }
+    match Action::from_name(command) {
+        Some(action) => dispatch(state, action),
+        None => state.status = Some(format!("Unknown command: {}", command)),
+    }
+}
+
+/// Route typing to whichever prompt is open. Returns false if none is.
+fn edit_prompt(state: &mut State, action: Action) -> bool {
+    if state.selected_window == SelectedWindow::SearchPopup {
+        match action {
+            Action::Type(c) => state.push_search_char(c),
+            Action::DeleteChar => state.pop_search_char(),
+            Action::Submit => state.open_search_hit(),
+            Action::Cancel => state.close_search(),
+            _ => return false,
+        }
+    } else if let Some(command) = &mut state.command_line {
+        match action {
+            Action::Type(c) => command.push(c),
+            Action::DeleteChar => {
+                command.pop();
+            }
+            Action::Submit => {
+                let command = state.command_line.take().unwrap_or_default();
+                run_command(state, &command);
+            }
+            Action::Cancel => state.command_line = None,
+            _ => return false,
+        }
+    } else if state.find.editing {
+        match action {
+            Action::Type(c) => state.push_find_char(c),
+            Action::DeleteChar => state.pop_find_char(),
+            Action::Submit => state.keep_find(),
+            Action::Cancel => state.clear_find(),
+            _ => return false,
+        }
+    } else if state.filter.editing {
+        match action {
+            Action::Type(c) => state.push_filter_char(c),
+            Action::DeleteChar => state.pop_filter_char(),
+            Action::Submit => state.keep_filter(),
+            Action::Cancel => state.clear_filter(),
+            _ => return false,
+        }
+    } else {
+        return false;
+    }
+    true
+}
+
+/// Apply an action to the state. Movements are repeated by the count typed
+/// before them, which other actions ignore.
+pub fn dispatch(state: &mut State, action: Action) {
+    if let Action::Count(digit) = action {
+        let count = state.pending_count.unwrap_or(0);
+        state.pending_count =
+            Some(count.saturating_mul(10).saturating_add(digit as usize));
+        return;
+    }
+    let count = state.pending_count.take();
+    if edit_prompt(state, action) {
+        return;
+    }
+    let times = count.unwrap_or(1).min(isize::MAX as usize) as isize;
+    let half_page = (state.page_size() / 2).max(1) as isize;
+    let page = state.page_size() as isize;
+    match action {
+        Action::MoveDown => state.move_by(times),
+        Action::MoveUp => state.move_by(-times),
+        Action::HalfPageDown => state.move_by(half_page.saturating_mul(times)),
+        Action::HalfPageUp => state.move_by(-half_page.saturating_mul(times)),
+        Action::PageDown => state.move_by(page.saturating_mul(times)),
+        Action::PageUp => state.move_by(-page.saturating_mul(times)),
+        Action::GoToTop => {
+            state.move_to(count.map_or(0, |n| n.saturating_sub(1)))
+        }
+        Action::GoToBottom => {
+            state.move_to(count.map_or(usize::MAX, |n| n.saturating_sub(1)))
+        }
+        Action::MoveLeft => state.move_left(),
+        Action::MoveRight => state.move_right(),
+        Action::OpenLink => state.open_selected_entry_link(count),
+        Action::NextLink => state.cycle_link(times),
+        Action::PreviousLink => state.cycle_link(-times),
+        Action::ToggleRaw => state.switch_render_mode(),
+        Action::ToggleRead => state.toggle_selected_read(),
+        Action::MarkAllRead => state.mark_all_read(),
+        Action::ToggleStarred => state.toggle_selected_starred(),
+        Action::ToggleStarredView => state.switch_view(),
+        Action::ToggleSidebar => state.toggle_sidebar(),
+        Action::CycleSort => state.cycle_sort(),
+        Action::CycleGrouping => state.cycle_grouping(),
+        Action::Filter => state.open_filter(),
+        Action::Find => state.open_find(),
+        Action::NextMatch => state.next_match(),
+        Action::PreviousMatch => state.previous_match(),
+        Action::Search => state.open_search(),
+        Action::EditNote => state.request_note_edit(),
+        Action::Highlight if state.highlighting.is_some() => {
+            state.finish_highlighting()
+        }
+        Action::Highlight => state.start_highlighting(),
+        Action::Cancel if state.highlighting.is_some() => {
+            state.cancel_highlighting()
+        }
+        Action::Cancel if state.is_finding() => state.clear_find(),
+        Action::Cancel if state.selected_link.is_some() => {
+            state.selected_link = None
+        }
+        Action::Cancel if !state.filter.input.is_empty() => {
+            state.clear_filter()
+        }
+        Action::Cancel => {}
+        Action::ToggleHelp => match state.selected_window {
+            SelectedWindow::HelpPopup => {
+                state.selected_window = SelectedWindow::EntryList;
+            }
+            _ => state.selected_window = SelectedWindow::HelpPopup,
+        },
+        Action::CommandLine => state.command_line = Some(String::new()),
+        Action::Refresh => state.refresh_requested = true,
+        Action::Quit => state.quit_requested = true,
+        Action::Count(_)
+        | Action::Type(_)
+        | Action::DeleteChar
+        | Action::Submit => {}
+        Action::Click { column, row, width } => {
+            state.separators.dragging =
+                state.separators.divider_at(column, width);
+            if state.separators.dragging.is_none() {
+                state.click_at(column, row);
+            }
+        }
+        Action::Scroll { column, row, delta } => {
+            state.scroll_at(column, row, delta)
+        }
+        Action::DragDivider { column, width } => {
+            if let Some(index) = state.separators.dragging {
+                state.separators.update_position(index, column, width);
+            }
+        }
+        Action::ReleaseDivider => state.separators.dragging = None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, MarkRead};
+    use crate::entry_state::EntryStates;
+    use crate::feed::CachedFeed;
+    use ratatui::layout::Rect;
+
+    const CONTENT_NS: &str = "http://purl.org/rss/1.0/modules/content/";
+
+    /// A state showing a feed of `count` entries, newest first, which writes
+    /// nothing to disk.
+    fn state_with_entries(count: usize, config: Config) -> State {
+        let items: String = (0..count)
+            .map(|i| {
+                let paragraphs = "<p>Some text of the entry.</p>".repeat(40);
+                format!(
+                    "<item><guid>{i}</guid><title>Entry {i}</title>\
+                     <pubDate>Mon, {:02} Jun 2025 00:00:00 GMT</pubDate>\
+                     <content:encoded><![CDATA[{paragraphs}]]>\
+                     </content:encoded>\
+                     </item>",
+                    28 - i
+                )
+            })
+            .collect();
+        let rss = format!(
+            "<rss version=\"2.0\" xmlns:content=\"{CONTENT_NS}\">\
+             <channel><title>Test</title>\
+             {items}</channel></rss>"
+        );
+        let feed = feed_rs::parser::parse(rss.as_bytes()).unwrap();
+        let feeds = vec![CachedFeed {
+            url: "https://example.com/feed".to_string(),
+            feed,
+            bodies: Default::default(),
+            first_seen: Default::default(),
+        }];
+        let mut state = State::new(feeds, EntryStates::default(), config);
+        state.read_only = true;
+        state.loading = false;
+        state.list_area = Rect::new(0, 0, 40, 20);
+        state.content_area = Rect::new(0, 0, 40, 10);
+        state
+    }
+
+    fn run(state: &mut State, actions: &[Action]) {
+        for &action in actions {
+            dispatch(state, action);
+        }
+    }
+
+    fn selected_title(state: &State) -> String {
+        state
+            .selected()
+            .map_or(String::new(), |e| e.title().to_string())
+    }
+
+    #[test]
+    fn count_repeats_movement() {
+        let mut state = state_with_entries(10, Config::default());
+        run(&mut state, &[Action::Count(5), Action::MoveDown]);
+        assert_eq!(state.selected_entry, 5);
+        assert_eq!(selected_title(&state), "Entry 5");
+        assert_eq!(state.pending_count, None);
+        run(&mut state, &[Action::MoveUp, Action::MoveUp]);
+        assert_eq!(state.selected_entry, 3);
+        run(&mut state, &[Action::Count(2), Action::Count(0)]);
+        assert_eq!(state.pending_count, Some(20));
+        run(&mut state, &[Action::MoveDown]);
+        assert_eq!(state.selected_entry, 9);
+    }
+
+    #[test]
+    fn go_to_top_and_bottom() {
+        let mut state = state_with_entries(10, Config::default());
+        run(&mut state, &[Action::GoToBottom]);
+        assert_eq!(state.selected_entry, 9);
+        run(&mut state, &[Action::GoToTop]);
+        assert_eq!(state.selected_entry, 0);
+        run(&mut state, &[Action::Count(4), Action::GoToTop]);
+        assert_eq!(state.selected_entry, 3);
+    }
+
+    #[test]
+    fn counts_scroll_the_content() {
+        let mut state = state_with_entries(3, Config::default());
+        run(&mut state, &[Action::MoveRight]);
+        assert_eq!(state.selected_window, SelectedWindow::EntryContent);
+        run(&mut state, &[Action::Count(5), Action::MoveDown]);
+        assert_eq!(state.entry_scroll_offset, 5);
+        run(&mut state, &[Action::Count(9), Action::Count(9)]);
+        run(&mut state, &[Action::MoveUp]);
+        assert_eq!(state.entry_scroll_offset, 0);
+        // Scrolling stops once the last line is at the bottom of the pane
+        run(&mut state, &[Action::GoToBottom, Action::MoveDown]);
+        let bottom = state.entry_scroll_offset;
+        assert!(bottom > 0);
+        run(&mut state, &[Action::PageDown]);
+        assert_eq!(state.entry_scroll_offset, bottom);
+        run(&mut state, &[Action::MoveLeft, Action::MoveDown]);
+        assert_eq!(state.selected_entry, 1);
+        assert_eq!(state.entry_scroll_offset, 0);
+    }
+
+    #[test]
+    fn huge_counts_saturate() {
+        let mut state = state_with_entries(3, Config::default());
+        let digits = [Action::Count(9); 25];
+        for movement in [Action::PageDown, Action::HalfPageUp, Action::MoveUp] {
+            run(&mut state, &digits);
+            run(&mut state, &[movement]);
+        }
+        assert_eq!(selected_title(&state), "Entry 0");
+        run(&mut state, &[Action::MoveRight, Action::GoToBottom]);
+        let bottom = state.entry_scroll_offset;
+        run(&mut state, &[Action::GoToTop]);
+        run(&mut state, &digits);
+        run(&mut state, &[Action::PageDown]);
+        assert_eq!(state.entry_scroll_offset, bottom);
+    }
+
+    #[test]
+    fn dwelling_marks_read_once() {
+        let config = Config {
+            mark_read: MarkRead::Dwell,
+            mark_read_dwell_secs: 0,
+            ..Config::default()
+        };
+        let mut state = state_with_entries(3, config);
+        let is_selected_read =
+            |state: &State| state.selected().is_some_and(|e| state.is_read(e));
+        assert!(!is_selected_read(&state));
+        state.tick();
+        assert!(is_selected_read(&state));
+        // Marking the entry unread by hand is not undone by dwelling on it
+        run(&mut state, &[Action::ToggleRead]);
+        state.tick();
+        assert!(!is_selected_read(&state));
+        run(&mut state, &[Action::MoveDown]);
+        state.tick();
+        assert!(is_selected_read(&state));
+        run(&mut state, &[Action::MoveUp]);
+        state.tick();
+        assert!(is_selected_read(&state));
+    }
+
+    #[test]
+    fn cancel_clears_the_filter() {
+        let mut state = state_with_entries(12, Config::default());
+        run(&mut state, &[Action::Filter]);
+        for c in "entry 1".chars() {
+            run(&mut state, &[Action::Type(c)]);
+        }
+        // Entries 1, 10 and 11
+        assert_eq!(state.visible.len(), 3);
+        run(&mut state, &[Action::Submit, Action::MoveDown]);
+        assert!(!state.filter.editing);
+        assert_eq!(selected_title(&state), "Entry 10");
+        run(&mut state, &[Action::Cancel]);
+        assert!(state.filter.input.is_empty());
+        assert_eq!(state.visible.len(), 12);
+        assert_eq!(selected_title(&state), "Entry 10");
+    }
+
+    #[test]
+    fn cancel_forgets_the_picked_link_before_the_filter() {
+        let mut state = state_with_entries(12, Config::default());
+        run(
+            &mut state,
+            &[Action::Filter, Action::Type('1'), Action::Submit],
+        );
+        state.selected_link = Some(0);
+        run(&mut state, &[Action::Cancel]);
+        assert_eq!(state.selected_link, None);
+        assert_eq!(state.filter.input, "1");
+        run(&mut state, &[Action::Cancel]);
+        assert!(state.filter.input.is_empty());
+    }
+
+    #[test]
+    fn highlights_are_kept_apart_from_repeated_text() {
+        let mut state = state_with_entries(1, Config::default());
+        let lines: Vec<String> = state
+            .selected_document()
+            .lines
+            .iter()
+            .map(|line| line.spans.iter().map(|s| s.content.as_ref()).collect())
+            .collect();
+        let highlight =
+            [Action::Highlight, Action::MoveDown, Action::Highlight];
+        run(&mut state, &[Action::MoveRight, Action::Count(4)]);
+        run(&mut state, &[Action::MoveDown]);
+        run(&mut state, &highlight);
+        run(&mut state, &[Action::GoToTop]);
+        run(&mut state, &highlight);
+        let ranges: Vec<_> = state
+            .selected_highlights()
+            .iter()
+            .map(|h| h.range(&lines))
+            .collect();
+        assert_eq!(ranges, [Some((4, 5)), Some((0, 1))]);
+        // Highlighting within a highlight removes it, and only it
+        run(&mut state, &[Action::Count(4), Action::MoveDown]);
+        run(&mut state, &[Action::Highlight, Action::Highlight]);
+        assert_eq!(state.selected_highlights().len(), 1);
+        assert_eq!(state.selected_highlights()[0].line, 0);
+    }
+
+    #[test]
+    fn cancel_closes_the_command_line() {
+        let mut state = state_with_entries(3, Config::default());
+        run(&mut state, &[Action::CommandLine, Action::Type('q')]);
+        assert_eq!(state.command_line.as_deref(), Some("q"));
+        run(&mut state, &[Action::Cancel]);
+        assert_eq!(state.command_line, None);
+        assert!(!state.quit_requested);
+    }
+}
@@ -0,0 +1,484 @@
+use crate::feed::CachedFeed;
+use crate::storage;
+use bincode::Options;
+use chrono::{DateTime, Utc};
+use feed_rs::model::{
+    Category, Content, Entry, Feed, Link, MediaObject, Person, Text,
+};
+use mediatype::MediaTypeBuf;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+const MAGIC: &[u8; 4] = b"REMY";
+
+/// Length of the magic bytes, version and index length at the start of the
+/// file.
+const HEADER_LEN: u64 = 16;
+
+/// The most LZ4 can expand its input, as each byte of a match length adds
+/// at most 255 bytes to the output.
+const MAX_LZ4_RATIO: usize = 255;
+
+/// The parts of an entry which are only needed to display it, stored apart
+/// from the rest of the cache.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Body {
+    pub content: Option<Content>,
+    pub summary: Option<Text>,
+    pub media: Vec<MediaObject>,
+}
+
+impl Body {
+    /// The content, falling back to the summary.
+    pub fn text(&self) -> Option<&str> {
+        self.content
+            .as_ref()
+            .and_then(|c| c.body.as_deref())
+            .or(self.summary.as_ref().map(|s| s.content.as_str()))
+    }
+}
+
+/// Where the compressed body of an entry is in the cache file.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+struct BodyRef {
+    /// Offset from the start of the bodies section.
+    offset: u64,
+    len: u32,
+    /// Length of the body text, no longer read but kept in the format.
+    text_len: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredText {
+    content_type: String,
+    src: Option<String>,
+    content: String,
+}
+
+/// The metadata of an entry, in a form which is quicker to decode than
+/// `Entry` as dates and media types are not parsed from strings.
+#[derive(Serialize, Deserialize)]
+struct StoredEntry {
+    id: String,
+    title: Option<StoredText>,
+    /// Microseconds since the Unix epoch.
+    updated: Option<i64>,
+    authors: Vec<Person>,
+    links: Vec<Link>,
+    categories: Vec<Category>,
+    contributors: Vec<Person>,
+    published: Option<i64>,
+    source: Option<String>,
+    rights: Option<StoredText>,
+    language: Option<String>,
+    base: Option<String>,
+    body: BodyRef,
+}
+
+/// A feed whose entries have had their bodies taken out, along with where
+/// each body is stored.
+#[derive(Serialize, Deserialize)]
+struct IndexedFeed {
+    url: String,
+    /// The feed without its entries.
+    feed: Feed,
+    entries: Vec<StoredEntry>,
+    /// Microseconds since the Unix epoch at which each entry was first
+    /// fetched, keyed by entry id.
+    first_seen: HashMap<String, i64>,
+}
+
+/// An `IndexedFeed` as written in version 3, without first-seen times.
+#[derive(Deserialize)]
+struct IndexedFeedV3 {
+    url: String,
+    feed: Feed,
+    entries: Vec<StoredEntry>,
+}
+
+/// The first binary version, which is still read.
+const FIRST_BINARY_VERSION: u32 = 3;
+
+/// Parses media types, reusing earlier results as feeds use only a few.
+#[derive(Default)]
+struct MediaTypes(HashMap<String, MediaTypeBuf>);
+
+impl MediaTypes {
+    fn text(&mut self, text: StoredText) -> Text {
+        let content_type = match self.0.get(&text.content_type) {
+            Some(content_type) => content_type.clone(),
+            None => {
+                let parsed: MediaTypeBuf =
+                    text.content_type.parse().unwrap_or_else(|_| {
+                        mediatype::media_type!(TEXT / PLAIN).into()
+                    });
+                self.0.insert(text.content_type, parsed.clone());
+                parsed
+            }
+        };
+        Text {
+            content_type,
+            src: text.src,
+            content: text.content,
+        }
+    }
+}
+
+fn stored_text(text: Text) -> StoredText {
+    StoredText {
+        content_type: text.content_type.to_string(),
+        src: text.src,
+        content: text.content,
+    }
+}
+
+fn to_micros(date: Option<DateTime<Utc>>) -> Option<i64> {
+    date.map(|d| d.timestamp_micros())
+}
+
+fn from_micros(micros: Option<i64>) -> Option<DateTime<Utc>> {
+    micros.and_then(DateTime::from_timestamp_micros)
+}
+
+impl StoredEntry {
+    /// Split an entry into its metadata and body.
+    fn new(entry: Entry, body: BodyRef) -> Self {
+        StoredEntry {
+            id: entry.id,
+            title: entry.title.map(stored_text),
+            updated: to_micros(entry.updated),
+            authors: entry.authors,
+            links: entry.links,
+            categories: entry.categories,
+            contributors: entry.contributors,
+            published: to_micros(entry.published),
+            source: entry.source,
+            rights: entry.rights.map(stored_text),
+            language: entry.language,
+            base: entry.base,
+            body,
+        }
+    }
+
+    /// The entry without its body.
+    fn into_entry(self, media_types: &mut MediaTypes) -> Entry {
+        Entry {
+            id: self.id,
+            title: self.title.map(|t| media_types.text(t)),
+            updated: from_micros(self.updated),
+            authors: self.authors,
+            content: None,
+            links: self.links,
+            summary: None,
+            categories: self.categories,
+            contributors: self.contributors,
+            published: from_micros(self.published),
+            source: self.source,
+            rights: self.rights.map(|t| media_types.text(t)),
+            media: vec![],
+            language: self.language,
+            base: self.base,
+        }
+    }
+}
+
+/// Fill `buf` from `offset` in `file` without moving a cursor shared with
+/// other readers.
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+/// Fill `buf` from `offset` in `file`. Each read is positioned, so readers
+/// sharing the file do not need to agree on where its cursor is.
+#[cfg(windows)]
+fn read_exact_at(
+    file: &File,
+    mut buf: &mut [u8],
+    mut offset: u64,
+) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Bodies of the entries of a feed left on disk when the cache was loaded,
+/// read when they are first needed.
+#[derive(Clone, Default)]
+pub struct Bodies {
+    file: Option<Arc<File>>,
+    /// Offset of the bodies section in the file.
+    base: u64,
+    refs: Arc<HashMap<String, BodyRef>>,
+}
+
+impl Bodies {
+    /// Whether the body of an entry is stored on disk.
+    pub fn contains(&self, entry_id: &str) -> bool {
+        self.refs.contains_key(entry_id)
+    }
+
+    /// These bodies without those of the given entries, which have been
+    /// replaced by entries held in memory.
+    pub fn excluding(&self, entry_ids: &HashSet<&str>) -> Bodies {
+        let refs = self
+            .refs
+            .iter()
+            .filter(|(id, _)| !entry_ids.contains(id.as_str()))
+            .map(|(id, body_ref)| (id.clone(), *body_ref))
+            .collect();
+        Bodies {
+            file: self.file.clone(),
+            base: self.base,
+            refs: Arc::new(refs),
+        }
+    }
+
+    /// Read the body of an entry, or `None` if it is not stored on disk.
+    pub fn get(&self, entry_id: &str) -> Option<Body> {
+        let file = self.file.as_ref()?;
+        let body_ref = self.refs.get(entry_id)?;
+        let read = || -> Result<Body, io::Error> {
+            let mut compressed = vec![0; body_ref.len as usize];
+            read_exact_at(file, &mut compressed, self.base + body_ref.offset)?;
+            let (size, compressed) =
+                lz4_flex::block::uncompressed_size(&compressed)
+                    .map_err(invalid_data)?;
+            if size > compressed.len().saturating_mul(MAX_LZ4_RATIO) {
+                return Err(invalid_data(
+                    "body is larger than its compressed size allows",
+                ));
+            }
+            let bytes =
+                lz4_flex::decompress(compressed, size).map_err(invalid_data)?;
+            deserialize(&bytes).map_err(invalid_data)
+        };
+        read()
+            .inspect_err(|e| {
+                log::error!("Failed to read body of {}: {}", entry_id, e);
+            })
+            .ok()
+    }
+
+    /// Total size of the compressed bodies on disk.
+    pub fn disk_size(&self) -> u64 {
+        self.refs.values().map(|r| r.len as u64).sum()
+    }
+}
+
+/// The body of an entry, whether it is held in memory or on disk.
+pub fn entry_body(entry: &Entry, bodies: &Bodies) -> Body {
+    if bodies.contains(&entry.id) {
+        bodies.get(&entry.id).unwrap_or_default()
+    } else {
+        Body {
+            content: entry.content.clone(),
+            summary: entry.summary.clone(),
+            media: entry.media.clone(),
+        }
+    }
+}
+
+fn invalid_data<E>(e: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Decode bincode as `bincode::deserialize` does, but reading no more than
+/// `bytes` holds, so that a corrupt length cannot make it allocate more.
+fn deserialize<'a, T: Deserialize<'a>>(bytes: &'a [u8]) -> bincode::Result<T> {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(bytes.len() as u64)
+        .deserialize(bytes)
+}
+
+/// Decode the index of each feed, spread across threads as this dominates
+/// the time taken to load the cache.
+fn decode_index(
+    index: &[Vec<u8>],
+    version: u32,
+) -> Result<Vec<IndexedFeed>, io::Error> {
+    let decode = |bytes: &Vec<u8>| -> Result<IndexedFeed, bincode::Error> {
+        if version > FIRST_BINARY_VERSION {
+            return deserialize(bytes);
+        }
+        let feed: IndexedFeedV3 = deserialize(bytes)?;
+        Ok(IndexedFeed {
+            url: feed.url,
+            feed: feed.feed,
+            entries: feed.entries,
+            first_seen: HashMap::new(),
+        })
+    };
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = index.len().div_ceil(threads).max(1);
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = index
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(|| {
+                    chunk
+                        .iter()
+                        .map(decode)
+                        .collect::<Result<Vec<IndexedFeed>, _>>()
+                })
+            })
+            .collect();
+        let mut feeds = vec![];
+        for handle in handles {
+            let chunk = handle
+                .join()
+                .map_err(|_| io::Error::other("cache decoding panicked"))?;
+            feeds.extend(chunk.map_err(invalid_data)?);
+        }
+        Ok(feeds)
+    })
+}
+
+/// Read the cache written by `write`, leaving entry bodies on disk. Caches
+/// written by earlier binary versions are also read.
+pub fn read(path: &Path, version: u32) -> Result<Vec<CachedFeed>, io::Error> {
+    let mut file = File::open(path)?;
+    let mut header = [0; HEADER_LEN as usize];
+    file.read_exact(&mut header)?;
+    if &header[..4] != MAGIC {
+        return Err(invalid_data("not a remy cache file"));
+    }
+    let file_version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    if file_version > version {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "written by a newer version of remy (version {}, newer than \
+                 {})",
+                file_version, version
+            ),
+        ));
+    }
+    if file_version < FIRST_BINARY_VERSION {
+        return Err(invalid_data(format!(
+            "unsupported version {}, older than {}",
+            file_version, FIRST_BINARY_VERSION
+        )));
+    }
+    let index_len = u64::from_le_bytes(header[8..16].try_into().unwrap());
+    let file_len = file.metadata()?.len();
+    if HEADER_LEN.saturating_add(index_len) > file_len {
+        return Err(invalid_data("index is longer than the file"));
+    }
+    let mut index = vec![0; index_len as usize];
+    file.read_exact(&mut index)?;
+    let index: Vec<Vec<u8>> = deserialize(&index).map_err(invalid_data)?;
+    let feeds = decode_index(&index, file_version)?;
+    let base = HEADER_LEN + index_len;
+    let outside = |body: &BodyRef| {
+        base.checked_add(body.offset)
+            .and_then(|start| start.checked_add(body.len as u64))
+            .is_none_or(|end| end > file_len)
+    };
+    if feeds
+        .iter()
+        .flat_map(|feed| &feed.entries)
+        .any(|entry| outside(&entry.body))
+    {
+        return Err(invalid_data("entry body is outside the file"));
+    }
+    let file = Arc::new(file);
+    let mut media_types = MediaTypes::default();
+    Ok(feeds
+        .into_iter()
+        .map(|indexed| {
+            let mut feed = indexed.feed;
+            let mut refs = HashMap::new();
+            for stored in indexed.entries {
+                refs.insert(stored.id.clone(), stored.body);
+                feed.entries.push(stored.into_entry(&mut media_types));
+            }
+            CachedFeed {
+                url: indexed.url,
+                feed,
+                bodies: Bodies {
+                    file: Some(file.clone()),
+                    base,
+                    refs: Arc::new(refs),
+                },
+                first_seen: indexed
+                    .first_seen
+                    .into_iter()
+                    .filter_map(|(id, micros)| {
+                        Some((id, DateTime::from_timestamp_micros(micros)?))
+                    })
+                    .collect(),
+            }
+        })
+        .collect())
+}
+
+/// Atomically write `feeds` to `path`. Entry metadata is written first, one
+/// feed at a time, so that it can be read without the bodies, which are
+/// compressed one by one.
+pub fn write(
+    path: &Path,
+    version: u32,
+    feeds: &[CachedFeed],
+) -> Result<(), io::Error> {
+    let mut index = vec![];
+    let mut bodies = vec![];
+    for cached in feeds {
+        let mut feed = cached.feed.clone();
+        let mut entries = vec![];
+        for entry in std::mem::take(&mut feed.entries) {
+            let body = entry_body(&entry, &cached.bodies);
+            let bytes = bincode::serialize(&body).map_err(invalid_data)?;
+            let compressed = lz4_flex::compress_prepend_size(&bytes);
+            let body_ref = BodyRef {
+                offset: bodies.len() as u64,
+                len: compressed.len() as u32,
+                text_len: body.text().map_or(0, str::len) as u32,
+            };
+            bodies.extend_from_slice(&compressed);
+            entries.push(StoredEntry::new(entry, body_ref));
+        }
+        let indexed = IndexedFeed {
+            url: cached.url.clone(),
+            feed,
+            entries,
+            first_seen: cached
+                .first_seen
+                .iter()
+                .map(|(id, date)| (id.clone(), date.timestamp_micros()))
+                .collect(),
+        };
+        index.push(bincode::serialize(&indexed).map_err(invalid_data)?);
+    }
+    let index = bincode::serialize(&index).map_err(invalid_data)?;
+    let temp_path = storage::temp_path(path);
+    let mut writer = BufWriter::new(File::create(&temp_path)?);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&version.to_le_bytes())?;
+    writer.write_all(&(index.len() as u64).to_le_bytes())?;
+    writer.write_all(&index)?;
+    writer.write_all(&bodies)?;
+    writer.flush()?;
+    drop(writer);
+    std::fs::rename(temp_path, path)?;
+    Ok(())
+}
@@ -0,0 +1,235 @@
+use crate::export::{self, Format, Selection};
+use crate::feed::{self, CachedFeed};
+use crate::state::entries_from_feeds;
+use crate::{config, entry_state, lock, retention, search};
+use clap::{Parser, Subcommand};
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(version, about = "Terminal-based RSS aggregator")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Inspect or prune the feed cache
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+    /// Export entries to Markdown, HTML or EPUB
+    Export {
+        #[arg(short, long, value_enum, default_value_t = Format::Html)]
+        format: Format,
+        /// Only export starred entries
+        #[arg(long)]
+        starred: bool,
+        /// Only export entries from this feed, given by URL or title
+        #[arg(long)]
+        feed: Option<String>,
+        /// Only export entries from the last N days
+        #[arg(long)]
+        days: Option<u64>,
+        /// File to write, defaults to remy-export.<extension>
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Search the titles, authors, categories, content, notes and highlights
+    /// of cached entries
+    Search {
+        query: Vec<String>,
+        /// Maximum number of results
+        #[arg(short = 'n', long, default_value_t = 10)]
+        limit: usize,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommand {
+    /// Report the number of entries and size of each cached feed
+    Stats,
+    /// Remove entries outside the retention policy
+    Prune,
+}
+
+pub async fn run(command: Command) -> Result<(), io::Error> {
+    match command {
+        Command::Cache { command } => match command {
+            CacheCommand::Stats => cache_stats().await,
+            CacheCommand::Prune => cache_prune().await,
+        },
+        Command::Export {
+            format,
+            starred,
+            feed,
+            days,
+            output,
+        } => {
+            let selection = Selection {
+                starred,
+                feed,
+                days,
+            };
+            let output = output.unwrap_or_else(|| {
+                PathBuf::from(format!("remy-export.{}", format.extension()))
+            });
+            export_entries(format, &selection, &output).await
+        }
+        Command::Search { query, limit } => {
+            search_entries(&query.join(" "), limit).await
+        }
+    }
+}
+
+fn load_entry_states(
+    read_only: bool,
+) -> Result<entry_state::EntryStates, io::Error> {
+    match entry_state::load(read_only) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Default::default()),
+        result => result,
+    }
+}
+
+async fn load_cached_feeds(
+    read_only: bool,
+) -> Result<Vec<CachedFeed>, io::Error> {
+    match feed::load_cached_feeds(read_only).await {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        result => result,
+    }
+}
+
+async fn search_entries(query: &str, limit: usize) -> Result<(), io::Error> {
+    let lock = lock::acquire()?;
+    let read_only = lock.is_none();
+    let entry_states = load_entry_states(read_only)?;
+    let feeds = load_cached_feeds(read_only).await?;
+    let entries = entries_from_feeds(&feeds, &entry_states);
+    let mut index = match search::load(read_only) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Default::default(),
+        result => result?,
+    };
+    if index.update(&entries, &entry_states.annotations()) && !read_only {
+        search::save(&index)?;
+    }
+    for (rank, result) in index.query(query, limit).iter().enumerate() {
+        let Some(entry) = entries.iter().find(|e| e.key() == result.key) else {
+            continue;
+        };
+        let text = search::html_to_text(entry.body().unwrap_or(""));
+        let (snippet, _) = search::snippet(&text, query, 100);
+        let date = entry
+            .date()
+            .map(|d| d.format("%d/%m/%Y").to_string())
+            .unwrap_or_else(|| "Unknown Date".to_string());
+        println!("{}. {}", rank + 1, entry.title());
+        println!("   {} - {}", entry.author, date);
+        if let Some(link) = entry.entry.links.first() {
+            println!("   {}", link.href);
+        }
+        println!("   {}\n", snippet.trim());
+    }
+    Ok(())
+}
+
+async fn export_entries(
+    format: Format,
+    selection: &Selection,
+    output: &std::path::Path,
+) -> Result<(), io::Error> {
+    let lock = lock::acquire()?;
+    let entry_states = load_entry_states(lock.is_none())?;
+    let feeds = load_cached_feeds(lock.is_none()).await?;
+    let entries = entries_from_feeds(&feeds, &entry_states);
+    let selected = export::select(&entries, &feeds, &entry_states, selection);
+    export::export(&selected, &entry_states, format, output)?;
+    println!(
+        "Exported {} entries to {}",
+        selected.len(),
+        output.display()
+    );
+    Ok(())
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+fn cache_file_size() -> u64 {
+    feed::get_cache_path()
+        .and_then(std::fs::metadata)
+        .map(|m| m.len())
+        .unwrap_or(0)
+}
+
+/// Approximate size of a feed within the cache file.
+fn feed_size(feed: &CachedFeed) -> u64 {
+    bincode::serialized_size(&feed.feed).unwrap_or(0) + feed.bodies.disk_size()
+}
+
+async fn cache_stats() -> Result<(), io::Error> {
+    let lock = lock::acquire()?;
+    let feeds = feed::load_cached_feeds(lock.is_none()).await?;
+    println!("{:<60} {:>8} {:>10}", "Feed", "Entries", "Size");
+    let mut total_entries = 0;
+    for cached in &feeds {
+        let title = cached
+            .feed
+            .title
+            .as_ref()
+            .map_or(cached.url.as_str(), |t| t.content.as_str());
+        let entries = cached.feed.entries.len();
+        total_entries += entries;
+        println!(
+            "{:<60} {:>8} {:>10}",
+            title.chars().take(60).collect::<String>(),
+            entries,
+            format_size(feed_size(cached))
+        );
+    }
+    println!(
+        "{:<60} {:>8} {:>10}",
+        "Total",
+        total_entries,
+        format_size(cache_file_size())
+    );
+    Ok(())
+}
+
+async fn cache_prune() -> Result<(), io::Error> {
+    let Some(_lock) = lock::acquire()? else {
+        return Err(io::Error::new(
+            io::ErrorKind::ResourceBusy,
+            "remy is already running, close it before pruning the cache",
+        ));
+    };
+    let config = config::load()?;
+    let entry_states = load_entry_states(false)?;
+    let mut feeds = feed::load_cached_feeds(false).await?;
+    let before = cache_file_size();
+    let keep =
+        retention::entries_to_keep(&feeds, &entry_states, &config.retention);
+    let removed = retention::prune(&mut feeds, &config.retention, &keep);
+    feed::save_cached_feeds(feeds).await?;
+    let after = cache_file_size();
+    println!(
+        "Removed {} entries, reclaimed {}",
+        removed.len(),
+        format_size(before.saturating_sub(after))
+    );
+    Ok(())
+}
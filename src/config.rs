@@ -0,0 +1,166 @@
+use serde::Deserialize;
+use std::io;
+
+const CONFIG_PREFIX: &str = "com.benmandrew.remy";
+const CONFIG_FILENAME: &str = "config.toml";
+
+/// When an entry is marked as read.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MarkRead {
+    /// As soon as the entry is selected in the list.
+    Select,
+    /// After the entry has stayed selected for `mark_read_dwell_secs`.
+    #[default]
+    Dwell,
+    /// When the content pane is focused or the entry is opened in a browser.
+    Open,
+}
+
+/// The order of the entry list. Entries without a date are listed last.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    /// Newest first by publication date.
+    #[default]
+    Published,
+    /// Newest first by the date entries were last updated, so that edited
+    /// entries move to the top.
+    Updated,
+    /// Alphabetically by feed, then newest first.
+    Feed,
+    /// Alphabetically by title.
+    Title,
+    /// Unread entries, then read ones, each newest first.
+    Unread,
+}
+
+impl SortOrder {
+    /// The next order, in the order they are cycled through.
+    pub fn next(self) -> Self {
+        match self {
+            SortOrder::Published => SortOrder::Updated,
+            SortOrder::Updated => SortOrder::Feed,
+            SortOrder::Feed => SortOrder::Title,
+            SortOrder::Title => SortOrder::Unread,
+            SortOrder::Unread => SortOrder::Published,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SortOrder::Published => "published",
+            SortOrder::Updated => "updated",
+            SortOrder::Feed => "feed",
+            SortOrder::Title => "title",
+            SortOrder::Unread => "unread first",
+        }
+    }
+}
+
+/// How the entry list is split into sections with headers.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Grouping {
+    #[default]
+    None,
+    /// Today, yesterday, this week, then by month.
+    Date,
+    Feed,
+}
+
+impl Grouping {
+    /// The next grouping, in the order they are cycled through.
+    pub fn next(self) -> Self {
+        match self {
+            Grouping::None => Grouping::Date,
+            Grouping::Date => Grouping::Feed,
+            Grouping::Feed => Grouping::None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Grouping::None => "none",
+            Grouping::Date => "date",
+            Grouping::Feed => "feed",
+        }
+    }
+}
+
+/// How images in entries are shown.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageMode {
+    /// With the best protocol the terminal seems to support.
+    #[default]
+    Auto,
+    Kitty,
+    Sixel,
+    Iterm2,
+    /// With coloured Unicode half blocks, which any terminal can show.
+    Halfblocks,
+    /// As a placeholder with the image's alt text.
+    Off,
+}
+
+/// How long entries are kept in the cache.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Retention {
+    /// Entries older than this are pruned.
+    pub max_age_days: Option<u64>,
+    /// Only this many of the newest entries of each feed are kept.
+    pub max_entries_per_feed: Option<usize>,
+    /// Keep unread entries regardless of age or count. Starred entries are
+    /// always kept.
+    pub keep_unread: bool,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Retention {
+            max_age_days: Some(90),
+            max_entries_per_feed: Some(500),
+            keep_unread: true,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Config {
+    pub mark_read: MarkRead,
+    pub mark_read_dwell_secs: u64,
+    pub sort: SortOrder,
+    pub group_by: Grouping,
+    pub images: ImageMode,
+    pub retention: Retention,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            mark_read: MarkRead::default(),
+            mark_read_dwell_secs: 3,
+            sort: SortOrder::default(),
+            group_by: Grouping::default(),
+            images: ImageMode::default(),
+            retention: Retention::default(),
+        }
+    }
+}
+
+fn get_config_path() -> Option<std::path::PathBuf> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix(CONFIG_PREFIX);
+    xdg_dirs.find_config_file(CONFIG_FILENAME)
+}
+
+pub fn load() -> Result<Config, std::io::Error> {
+    let Some(config_path) = get_config_path() else {
+        return Ok(Config::default());
+    };
+    let content = std::fs::read_to_string(config_path)?;
+    toml::from_str(&content)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
@@ -0,0 +1,227 @@
+use crate::storage::{self, Migration};
+use feed_rs::model::Entry;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io;
+
+const DATA_PREFIX: &str = "com.benmandrew.remy";
+const DATA_FILENAME: &str = "entry_state.json";
+
+/// Per-entry user state which is not part of the upstream feed.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct EntryState {
+    #[serde(default)]
+    pub read: bool,
+    /// Snapshot of the entry taken when it was starred, so that it is kept
+    /// after it drops out of the upstream feed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub starred: Option<Entry>,
+    /// Markdown note written by the user.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// Passages of the rendered content highlighted by the user.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub highlights: Vec<Highlight>,
+}
+
+/// A passage of whole lines of the rendered content.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct Highlight {
+    /// The lines of the passage, joined by newlines.
+    pub text: String,
+    /// The line the passage started at when it was highlighted.
+    #[serde(default)]
+    pub line: usize,
+}
+
+impl Highlight {
+    /// The first and last of `lines` which the passage is, nearest to the
+    /// line it started at, as the same text may appear more than once or
+    /// lines may have been added before it since. Trailing whitespace is
+    /// ignored, as code blocks are padded to the width of the pane.
+    pub fn range(&self, lines: &[String]) -> Option<(usize, usize)> {
+        let passage: Vec<&str> =
+            self.text.split('\n').map(str::trim_end).collect();
+        let matches_at = |start: usize| {
+            lines[start..start + passage.len()]
+                .iter()
+                .zip(&passage)
+                .all(|(line, passage)| line.trim_end() == *passage)
+        };
+        let last_start = lines.len().checked_sub(passage.len())?;
+        let start = (0..=last_start)
+            .filter(|&start| matches_at(start))
+            .min_by_key(|&start| start.abs_diff(self.line))?;
+        Some((start, start + passage.len() - 1))
+    }
+}
+
+/// User state for every entry, keyed by feed URL and then entry id.
+#[derive(Serialize, Deserialize, Default)]
+pub struct EntryStates {
+    feeds: HashMap<String, HashMap<String, EntryState>>,
+}
+
+impl EntryStates {
+    pub fn get(&self, feed_url: &str, entry_id: &str) -> Option<&EntryState> {
+        self.feeds.get(feed_url).and_then(|f| f.get(entry_id))
+    }
+
+    fn get_mut(&mut self, feed_url: &str, entry_id: &str) -> &mut EntryState {
+        self.feeds
+            .entry(feed_url.to_string())
+            .or_default()
+            .entry(entry_id.to_string())
+            .or_default()
+    }
+
+    pub fn is_read(&self, feed_url: &str, entry_id: &str) -> bool {
+        self.get(feed_url, entry_id).is_some_and(|s| s.read)
+    }
+
+    /// Returns whether the read state changed.
+    pub fn set_read(
+        &mut self,
+        feed_url: &str,
+        entry_id: &str,
+        read: bool,
+    ) -> bool {
+        let state = self.get_mut(feed_url, entry_id);
+        let changed = state.read != read;
+        state.read = read;
+        changed
+    }
+
+    pub fn is_starred(&self, feed_url: &str, entry_id: &str) -> bool {
+        self.get(feed_url, entry_id)
+            .is_some_and(|s| s.starred.is_some())
+    }
+
+    pub fn set_starred(&mut self, feed_url: &str, entry: Entry, starred: bool) {
+        let state = self.get_mut(feed_url, &entry.id);
+        state.starred = starred.then_some(entry);
+    }
+
+    pub fn note(&self, feed_url: &str, entry_id: &str) -> Option<&str> {
+        self.get(feed_url, entry_id).and_then(|s| s.note.as_deref())
+    }
+
+    /// Set the note of an entry, removing it if it is blank.
+    pub fn set_note(&mut self, feed_url: &str, entry_id: &str, note: String) {
+        let state = self.get_mut(feed_url, entry_id);
+        state.note = (!note.trim().is_empty()).then_some(note);
+    }
+
+    pub fn highlights(&self, feed_url: &str, entry_id: &str) -> &[Highlight] {
+        self.get(feed_url, entry_id)
+            .map_or(&[], |s| s.highlights.as_slice())
+    }
+
+    /// Highlight `lines[start..=end]` of the rendered content, replacing any
+    /// highlights within them, or remove the highlights which already cover
+    /// them.
+    pub fn toggle_highlight(
+        &mut self,
+        feed_url: &str,
+        entry_id: &str,
+        lines: &[String],
+        (start, end): (usize, usize),
+    ) {
+        let highlights = &mut self.get_mut(feed_url, entry_id).highlights;
+        let before = highlights.len();
+        highlights.retain(|h| {
+            h.range(lines).is_none_or(|(s, e)| s > start || e < end)
+        });
+        if highlights.len() == before {
+            highlights.retain(|h| {
+                h.range(lines).is_none_or(|(s, e)| s < start || e > end)
+            });
+            highlights.push(Highlight {
+                text: lines[start..=end].join("\n"),
+                line: start,
+            });
+        }
+    }
+
+    /// The text of the notes and highlights of every annotated entry, keyed
+    /// by feed URL and entry id.
+    pub fn annotations(&self) -> HashMap<(String, String), String> {
+        let mut annotations = HashMap::new();
+        for (url, entries) in &self.feeds {
+            for (id, state) in entries {
+                let mut text = state.note.clone().unwrap_or_default();
+                for highlight in &state.highlights {
+                    text.push('\n');
+                    text.push_str(&highlight.text);
+                }
+                if !text.is_empty() {
+                    annotations.insert((url.clone(), id.clone()), text);
+                }
+            }
+        }
+        annotations
+    }
+
+    /// Every starred entry along with the URL of the feed it came from.
+    pub fn starred_entries(&self) -> impl Iterator<Item = (&str, &Entry)> {
+        self.feeds.iter().flat_map(|(url, entries)| {
+            entries
+                .values()
+                .filter_map(|s| s.starred.as_ref())
+                .map(move |e| (url.as_str(), e))
+        })
+    }
+}
+
+fn get_data_path() -> Result<std::path::PathBuf, std::io::Error> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix(DATA_PREFIX);
+    xdg_dirs.find_data_file(DATA_FILENAME).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "entry state not found")
+    })
+}
+
+/// Version of the on-disk entry state format.
+const DATA_VERSION: u32 = 3;
+
+/// Version 1 is the unversioned format, whose data is unchanged in version 2.
+/// Version 3 records the line each highlight started at.
+const DATA_MIGRATIONS: &[Migration] = &[Ok, anchor_highlights];
+
+/// Turn each highlighted passage into a highlight which is matched from the
+/// first line.
+fn anchor_highlights(mut data: Value) -> Result<Value, String> {
+    let feeds = data
+        .get_mut("feeds")
+        .and_then(Value::as_object_mut)
+        .ok_or("missing feeds")?;
+    for entries in feeds.values_mut() {
+        let entries = entries.as_object_mut().ok_or("invalid feed")?;
+        for state in entries.values_mut() {
+            let Some(highlights) =
+                state.get_mut("highlights").and_then(Value::as_array_mut)
+            else {
+                continue;
+            };
+            for highlight in highlights {
+                let text = highlight.take();
+                *highlight = serde_json::json!({ "text": text, "line": 0 });
+            }
+        }
+    }
+    Ok(data)
+}
+
+/// Load the entry state, without migrating it on disk if `read_only`.
+pub fn load(read_only: bool) -> Result<EntryStates, std::io::Error> {
+    let data_path = get_data_path()?;
+    storage::load(&data_path, DATA_VERSION, DATA_MIGRATIONS, read_only)
+}
+
+pub fn save(states: &EntryStates) -> Result<(), std::io::Error> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix(DATA_PREFIX);
+    let data_path = xdg_dirs
+        .place_data_file(DATA_FILENAME)
+        .map_err(io::Error::other)?;
+    storage::save(&data_path, DATA_VERSION, states)
+}
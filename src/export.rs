@@ -0,0 +1,731 @@
+use crate::entry_state::EntryStates;
+use crate::feed::CachedFeed;
+use crate::render::{
+    HtmlVisitor, is_block_element, is_whitespace_preserved,
+    normalize_whitespace, traverse_element,
+};
+use crate::retention;
+use crate::state::EntryWithAuthor;
+use scraper::Html;
+use scraper::node::Element;
+use std::io::{self, Write};
+use std::path::Path;
+use url::Url;
+
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq)]
+pub enum Format {
+    Markdown,
+    Html,
+    Epub,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Markdown => "md",
+            Format::Html => "html",
+            Format::Epub => "epub",
+        }
+    }
+}
+
+/// Which entries to export. Every given criterion must match.
+pub struct Selection {
+    pub starred: bool,
+    /// Feed URL or title.
+    pub feed: Option<String>,
+    pub days: Option<u64>,
+}
+
+pub fn select<'a>(
+    entries: &'a [EntryWithAuthor],
+    feeds: &[CachedFeed],
+    entry_states: &EntryStates,
+    selection: &Selection,
+) -> Vec<&'a EntryWithAuthor> {
+    let cutoff = selection.days.and_then(retention::cutoff);
+    let feed_url = selection.feed.as_ref().map(|name| {
+        feeds
+            .iter()
+            .find(|f| {
+                f.feed
+                    .title
+                    .as_ref()
+                    .is_some_and(|t| t.content.eq_ignore_ascii_case(name))
+            })
+            .map_or(name.as_str(), |f| f.url.as_str())
+    });
+    entries
+        .iter()
+        .filter(|e| {
+            !selection.starred
+                || entry_states.is_starred(&e.feed_url, &e.entry.id)
+        })
+        .filter(|e| feed_url.is_none_or(|url| e.feed_url == url))
+        .filter(|e| {
+            cutoff.is_none_or(|cutoff| {
+                e.date().is_some_and(|date| date >= cutoff)
+            })
+        })
+        .collect()
+}
+
+fn link(entry: &EntryWithAuthor) -> Option<&str> {
+    entry.entry.links.first().map(|l| l.href.as_str())
+}
+
+fn byline(entry: &EntryWithAuthor) -> String {
+    match entry.date() {
+        Some(date) => format!("{} - {}", entry.author, date.format("%d/%m/%Y")),
+        None => entry.author.clone(),
+    }
+}
+
+/// Resolve a possibly relative URL against the entry link.
+fn resolve(base: Option<&Url>, href: &str) -> String {
+    base.and_then(|base| base.join(href).ok())
+        .map_or_else(|| href.to_string(), |url| url.to_string())
+}
+
+fn is_skipped(tag_stack: &[String]) -> bool {
+    tag_stack.iter().any(|t| t == "script" || t == "style")
+}
+
+/// Converts entry content to Markdown.
+struct MarkdownWriter<'a> {
+    out: String,
+    base: Option<&'a Url>,
+    /// Item counter of each enclosing list, or `None` for unordered lists.
+    lists: Vec<Option<usize>>,
+    hrefs: Vec<Option<String>>,
+    quote_depth: usize,
+    at_line_start: bool,
+    /// Whether the current line holds nothing but a list marker.
+    at_item_start: bool,
+    /// A blank line is written before the next line of text, within the
+    /// blockquotes enclosing both lines.
+    pending_blank: Option<usize>,
+}
+
+impl<'a> MarkdownWriter<'a> {
+    fn new(base: Option<&'a Url>) -> Self {
+        MarkdownWriter {
+            out: String::new(),
+            base,
+            lists: vec![],
+            hrefs: vec![],
+            quote_depth: 0,
+            at_line_start: true,
+            at_item_start: false,
+            pending_blank: None,
+        }
+    }
+
+    fn line_prefix(&self) -> String {
+        "> ".repeat(self.quote_depth) + &"    ".repeat(self.lists.len())
+    }
+
+    fn start_line(&mut self, prefix: &str) {
+        if let Some(depth) = self.pending_blank.take()
+            && !self.out.is_empty()
+        {
+            let depth = depth.min(self.quote_depth);
+            self.out.push_str("> ".repeat(depth).trim_end());
+            self.out.push('\n');
+        }
+        self.out.push_str(prefix);
+        self.at_line_start = false;
+    }
+
+    fn write(&mut self, text: &str) {
+        if self.at_line_start {
+            self.start_line(&self.line_prefix());
+        }
+        self.out.push_str(text);
+        self.at_item_start = false;
+    }
+
+    fn end_line(&mut self) {
+        if !self.at_line_start {
+            self.out.push('\n');
+            self.at_line_start = true;
+        }
+        self.at_item_start = false;
+    }
+
+    fn end_paragraph(&mut self) {
+        self.end_line();
+        let depth = self.quote_depth;
+        self.pending_blank =
+            Some(self.pending_blank.map_or(depth, |d| d.min(depth)));
+    }
+
+    /// Blocks inside lists are separated by line breaks only, so that they
+    /// stay part of the list item.
+    fn end_block(&mut self) {
+        if self.lists.is_empty() {
+            self.end_paragraph();
+        } else if !self.at_item_start {
+            self.end_line();
+        }
+    }
+
+    fn escape(text: &str) -> String {
+        let mut escaped = String::with_capacity(text.len());
+        for ch in text.chars() {
+            if matches!(ch, '\\' | '*' | '_' | '`' | '[' | ']' | '<') {
+                escaped.push('\\');
+            }
+            escaped.push(ch);
+        }
+        escaped
+    }
+}
+
+impl HtmlVisitor for MarkdownWriter<'_> {
+    fn open_element(&mut self, element: &Element, tag_stack: &[String]) {
+        let in_pre = is_whitespace_preserved(tag_stack);
+        match element.name() {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.end_paragraph();
+                let level = element.name()[1..].parse().unwrap_or(1);
+                self.write(&format!("{} ", "#".repeat(level)));
+            }
+            "ul" => {
+                self.end_line();
+                self.lists.push(None);
+            }
+            "ol" => {
+                self.end_line();
+                let start = element.attr("start").and_then(|s| s.parse().ok());
+                self.lists.push(Some(start.unwrap_or(1)));
+            }
+            "li" => {
+                self.end_line();
+                let marker = match self.lists.last_mut() {
+                    Some(Some(n)) => {
+                        *n += 1;
+                        format!("{}. ", *n - 1)
+                    }
+                    _ => "- ".to_string(),
+                };
+                // The marker replaces the indentation of the current level
+                let depth = self.lists.len().saturating_sub(1);
+                let prefix = "> ".repeat(self.quote_depth)
+                    + &"    ".repeat(depth)
+                    + &marker;
+                self.start_line(&prefix);
+                self.at_item_start = true;
+            }
+            "blockquote" => {
+                self.end_paragraph();
+                self.quote_depth += 1;
+            }
+            "pre" => {
+                self.end_paragraph();
+                self.write("```");
+                self.end_line();
+            }
+            "code" if !in_pre => self.write("`"),
+            "b" | "strong" => self.write("**"),
+            "i" | "em" => self.write("*"),
+            "s" | "del" => self.write("~~"),
+            "a" => {
+                let href = element.attr("href").map(|h| resolve(self.base, h));
+                if href.is_some() {
+                    self.write("[");
+                }
+                self.hrefs.push(href);
+            }
+            "img" => {
+                if let Some(src) = element.attr("src") {
+                    let alt = element.attr("alt").unwrap_or("");
+                    let src = resolve(self.base, src);
+                    self.write(&format!("![{}]({})", Self::escape(alt), src));
+                }
+            }
+            "br" => {
+                self.write("  ");
+                self.end_line();
+            }
+            "hr" => {
+                self.end_paragraph();
+                self.write("---");
+                self.end_paragraph();
+            }
+            "tr" => self.end_line(),
+            "thead" | "tbody" | "tfoot" => {}
+            tag if is_block_element(tag) => self.end_block(),
+            _ => {}
+        }
+    }
+
+    fn close_element(&mut self, element: &Element, tag_stack: &[String]) {
+        let in_pre = is_whitespace_preserved(tag_stack);
+        match element.name() {
+            "ul" | "ol" => {
+                self.lists.pop();
+                if self.lists.is_empty() {
+                    self.end_paragraph();
+                }
+            }
+            "li" => self.end_line(),
+            "blockquote" => {
+                self.end_line();
+                self.quote_depth = self.quote_depth.saturating_sub(1);
+                self.end_paragraph();
+            }
+            "pre" => {
+                self.end_line();
+                self.write("```");
+                self.end_paragraph();
+            }
+            "code" if !in_pre => self.write("`"),
+            "b" | "strong" => self.write("**"),
+            "i" | "em" => self.write("*"),
+            "s" | "del" => self.write("~~"),
+            "a" => {
+                if let Some(Some(href)) = self.hrefs.pop() {
+                    self.write(&format!("]({})", href));
+                }
+            }
+            "td" | "th" => self.write(" "),
+            "tr" | "thead" | "tbody" | "tfoot" => self.end_line(),
+            tag if is_block_element(tag) => self.end_block(),
+            _ => {}
+        }
+    }
+
+    fn text(&mut self, text: &str, tag_stack: &[String]) {
+        if is_skipped(tag_stack) {
+            return;
+        }
+        if tag_stack.iter().any(|t| t == "pre") {
+            for (i, line) in text.split('\n').enumerate() {
+                if i > 0 {
+                    if self.at_line_start {
+                        self.start_line(self.line_prefix().trim_end());
+                    }
+                    self.end_line();
+                }
+                if !line.is_empty() {
+                    self.write(line);
+                }
+            }
+            return;
+        }
+        let mut normalized = normalize_whitespace(text, false);
+        if self.at_line_start {
+            normalized = normalized.trim_start().to_string();
+        }
+        if normalized.is_empty() {
+            return;
+        }
+        if tag_stack.iter().any(|t| t == "code") {
+            self.write(&normalized);
+        } else {
+            self.write(&Self::escape(&normalized));
+        }
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Converts entry content to well-formed XHTML, keeping only the elements
+/// which carry meaning outside of the original page.
+struct HtmlWriter<'a> {
+    out: String,
+    base: Option<&'a Url>,
+}
+
+fn is_kept_element(tag: &str) -> bool {
+    matches!(
+        tag,
+        "p" | "div"
+            | "h1"
+            | "h2"
+            | "h3"
+            | "h4"
+            | "h5"
+            | "h6"
+            | "ul"
+            | "ol"
+            | "li"
+            | "dl"
+            | "dt"
+            | "dd"
+            | "blockquote"
+            | "pre"
+            | "code"
+            | "b"
+            | "strong"
+            | "i"
+            | "em"
+            | "u"
+            | "s"
+            | "del"
+            | "sub"
+            | "sup"
+            | "a"
+            | "img"
+            | "br"
+            | "hr"
+            | "table"
+            | "thead"
+            | "tbody"
+            | "tr"
+            | "td"
+            | "th"
+            | "figure"
+            | "figcaption"
+    )
+}
+
+fn is_void_element(tag: &str) -> bool {
+    matches!(tag, "br" | "hr" | "img")
+}
+
+impl HtmlVisitor for HtmlWriter<'_> {
+    fn open_element(&mut self, element: &Element, tag_stack: &[String]) {
+        let tag = element.name();
+        if !is_kept_element(tag) || is_skipped(tag_stack) {
+            return;
+        }
+        self.out.push('<');
+        self.out.push_str(tag);
+        let attrs: &[&str] = match tag {
+            "a" => &["href"],
+            "img" => &["src", "alt"],
+            "ol" => &["start", "type"],
+            "td" | "th" => &["colspan", "rowspan"],
+            _ => &[],
+        };
+        for &name in attrs {
+            if let Some(value) = element.attr(name) {
+                let value = match name {
+                    "href" | "src" => resolve(self.base, value),
+                    _ => value.to_string(),
+                };
+                self.out.push_str(&format!(
+                    " {}=\"{}\"",
+                    name,
+                    escape_html(&value)
+                ));
+            }
+        }
+        self.out
+            .push_str(if is_void_element(tag) { "/>" } else { ">" });
+    }
+
+    fn close_element(&mut self, element: &Element, tag_stack: &[String]) {
+        let tag = element.name();
+        if is_kept_element(tag)
+            && !is_void_element(tag)
+            && !is_skipped(tag_stack)
+        {
+            self.out.push_str(&format!("</{}>", tag));
+        }
+    }
+
+    fn text(&mut self, text: &str, tag_stack: &[String]) {
+        if !is_skipped(tag_stack) {
+            self.out.push_str(&escape_html(text));
+        }
+    }
+}
+
+fn convert<V: HtmlVisitor>(entry: &EntryWithAuthor, visitor: &mut V) {
+    let html = Html::parse_fragment(entry.body().unwrap_or(""));
+    traverse_element(html.root_element(), &mut Vec::new(), visitor);
+}
+
+fn base_url(entry: &EntryWithAuthor) -> Option<Url> {
+    link(entry).and_then(|l| Url::parse(l).ok())
+}
+
+/// Markdown section with the note and highlights of an entry, if it has any.
+fn annotations_to_markdown(
+    entry: &EntryWithAuthor,
+    entry_states: &EntryStates,
+) -> String {
+    let mut out = String::new();
+    if let Some(note) = entry_states.note(&entry.feed_url, &entry.entry.id) {
+        out.push_str(&format!("\n\n## Notes\n\n{}", note.trim()));
+    }
+    let highlights = entry_states.highlights(&entry.feed_url, &entry.entry.id);
+    if !highlights.is_empty() {
+        out.push_str("\n\n## Highlights");
+        for highlight in highlights {
+            let quoted: Vec<String> =
+                highlight.text.lines().map(|l| format!("> {}", l)).collect();
+            out.push_str(&format!("\n\n{}", quoted.join("\n")));
+        }
+    }
+    out
+}
+
+fn annotations_to_html(
+    entry: &EntryWithAuthor,
+    entry_states: &EntryStates,
+) -> String {
+    let mut out = String::new();
+    if let Some(note) = entry_states.note(&entry.feed_url, &entry.entry.id) {
+        out.push_str("<section class=\"notes\">\n<h2>Notes</h2>\n");
+        for paragraph in note.split("\n\n").filter(|p| !p.trim().is_empty()) {
+            out.push_str(&format!(
+                "<p>{}</p>\n",
+                escape_html(paragraph.trim())
+            ));
+        }
+        out.push_str("</section>\n");
+    }
+    let highlights = entry_states.highlights(&entry.feed_url, &entry.entry.id);
+    if !highlights.is_empty() {
+        out.push_str("<section class=\"highlights\">\n<h2>Highlights</h2>\n");
+        for highlight in highlights {
+            let lines: Vec<String> =
+                highlight.text.lines().map(escape_html).collect();
+            out.push_str(&format!(
+                "<blockquote><p><mark>{}</mark></p></blockquote>\n",
+                lines.join("<br/>")
+            ));
+        }
+        out.push_str("</section>\n");
+    }
+    out
+}
+
+pub fn entry_to_markdown(
+    entry: &EntryWithAuthor,
+    entry_states: &EntryStates,
+) -> String {
+    let base = base_url(entry);
+    let mut writer = MarkdownWriter::new(base.as_ref());
+    convert(entry, &mut writer);
+    let mut out = format!("# {}\n\n*{}*", entry.title(), byline(entry));
+    if let Some(link) = link(entry) {
+        out.push_str(&format!(" · <{}>", link));
+    }
+    out.push_str("\n\n");
+    out.push_str(writer.out.trim());
+    out.push_str(&annotations_to_markdown(entry, entry_states));
+    out.push('\n');
+    out
+}
+
+fn entry_to_html(
+    entry: &EntryWithAuthor,
+    entry_states: &EntryStates,
+) -> String {
+    let base = base_url(entry);
+    let mut writer = HtmlWriter {
+        out: String::new(),
+        base: base.as_ref(),
+    };
+    convert(entry, &mut writer);
+    let heading = match link(entry) {
+        Some(link) => format!(
+            "<a href=\"{}\">{}</a>",
+            escape_html(link),
+            escape_html(entry.title())
+        ),
+        None => escape_html(entry.title()),
+    };
+    format!(
+        "<h1>{}</h1>\n<p class=\"byline\">{}</p>\n{}\n{}",
+        heading,
+        escape_html(&byline(entry)),
+        writer.out,
+        annotations_to_html(entry, entry_states)
+    )
+}
+
+const STYLE: &str = "body { max-width: 40em; margin: auto; \
+    font-family: sans-serif; line-height: 1.5; padding: 1em; } \
+    pre { overflow-x: auto; background: #f4f4f4; padding: 0.5em; } \
+    img { max-width: 100%; } \
+    .byline { font-style: italic; color: #555; } \
+    .notes, .highlights { border-top: 1px solid #ddd; }";
+
+fn to_markdown(
+    entries: &[&EntryWithAuthor],
+    entry_states: &EntryStates,
+) -> String {
+    entries
+        .iter()
+        .map(|e| entry_to_markdown(e, entry_states))
+        .collect::<Vec<_>>()
+        .join("\n---\n\n")
+}
+
+fn to_html(entries: &[&EntryWithAuthor], entry_states: &EntryStates) -> String {
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\"/>\n\
+         <title>remy export</title>\n<style>{}</style>\n</head>\n<body>\n\
+         <nav>\n<ol>\n",
+        STYLE
+    );
+    for (i, entry) in entries.iter().enumerate() {
+        out.push_str(&format!(
+            "<li><a href=\"#entry-{}\">{}</a></li>\n",
+            i,
+            escape_html(entry.title())
+        ));
+    }
+    out.push_str("</ol>\n</nav>\n");
+    for (i, entry) in entries.iter().enumerate() {
+        out.push_str(&format!(
+            "<article id=\"entry-{}\">\n{}</article>\n<hr/>\n",
+            i,
+            entry_to_html(entry, entry_states)
+        ));
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+fn xhtml_page(title: &str, body: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <!DOCTYPE html>\n\
+         <html xmlns=\"http://www.w3.org/1999/xhtml\" \
+         xmlns:epub=\"http://www.idpf.org/2007/ops\">\n\
+         <head>\n<meta charset=\"utf-8\"/>\n<title>{}</title>\n\
+         <style>{}</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        escape_html(title),
+        STYLE,
+        body
+    )
+}
+
+const CONTAINER_XML: &str = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+    <container version=\"1.0\" \
+    xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">\n\
+    <rootfiles>\n<rootfile full-path=\"OEBPS/content.opf\" \
+    media-type=\"application/oebps-package+xml\"/>\n</rootfiles>\n\
+    </container>\n";
+
+fn write_epub(
+    entries: &[&EntryWithAuthor],
+    entry_states: &EntryStates,
+    writer: impl Write + io::Seek,
+) -> zip::result::ZipResult<()> {
+    use zip::write::SimpleFileOptions;
+    let mut zip = zip::ZipWriter::new(writer);
+    let stored = SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Stored);
+    let deflated = SimpleFileOptions::default();
+    // The mimetype must come first and be stored uncompressed
+    zip.start_file("mimetype", stored)?;
+    zip.write_all(b"application/epub+zip")?;
+    zip.start_file("META-INF/container.xml", deflated)?;
+    zip.write_all(CONTAINER_XML.as_bytes())?;
+
+    let now = chrono::Utc::now();
+    let identifier = format!("urn:remy:export:{}", now.timestamp_millis());
+    let mut manifest = String::new();
+    let mut spine = String::new();
+    let mut nav = String::new();
+    let mut nav_points = String::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let name = format!("entry-{}.xhtml", i);
+        zip.start_file(format!("OEBPS/{}", name), deflated)?;
+        zip.write_all(
+            xhtml_page(entry.title(), &entry_to_html(entry, entry_states))
+                .as_bytes(),
+        )?;
+        manifest.push_str(&format!(
+            "<item id=\"entry-{}\" href=\"{}\" \
+             media-type=\"application/xhtml+xml\"/>\n",
+            i, name
+        ));
+        spine.push_str(&format!("<itemref idref=\"entry-{}\"/>\n", i));
+        nav.push_str(&format!(
+            "<li><a href=\"{}\">{}</a></li>\n",
+            name,
+            escape_html(entry.title())
+        ));
+        nav_points.push_str(&format!(
+            "<navPoint id=\"nav-{0}\" playOrder=\"{1}\">\
+             <navLabel><text>{2}</text></navLabel>\
+             <content src=\"{3}\"/></navPoint>\n",
+            i,
+            i + 1,
+            escape_html(entry.title()),
+            name
+        ));
+    }
+
+    zip.start_file("OEBPS/nav.xhtml", deflated)?;
+    let nav = format!(
+        "<nav epub:type=\"toc\" id=\"toc\">\n<h1>Contents</h1>\n<ol>\n{}\
+         </ol>\n</nav>\n",
+        nav
+    );
+    zip.write_all(xhtml_page("Contents", &nav).as_bytes())?;
+
+    zip.start_file("OEBPS/toc.ncx", deflated)?;
+    zip.write_all(
+        format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+             <ncx xmlns=\"http://www.daisy.org/z3986/2005/ncx/\" \
+             version=\"2005-1\">\n<head>\n\
+             <meta name=\"dtb:uid\" content=\"{}\"/>\n</head>\n\
+             <docTitle><text>remy export</text></docTitle>\n\
+             <navMap>\n{}</navMap>\n</ncx>\n",
+            identifier, nav_points
+        )
+        .as_bytes(),
+    )?;
+
+    zip.start_file("OEBPS/content.opf", deflated)?;
+    zip.write_all(
+        format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+             <package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" \
+             unique-identifier=\"uid\">\n\
+             <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n\
+             <dc:identifier id=\"uid\">{}</dc:identifier>\n\
+             <dc:title>remy export</dc:title>\n\
+             <dc:language>en</dc:language>\n\
+             <meta property=\"dcterms:modified\">{}</meta>\n\
+             </metadata>\n<manifest>\n\
+             <item id=\"nav\" href=\"nav.xhtml\" \
+             media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n\
+             <item id=\"ncx\" href=\"toc.ncx\" \
+             media-type=\"application/x-dtbncx+xml\"/>\n{}\
+             </manifest>\n<spine toc=\"ncx\">\n{}</spine>\n</package>\n",
+            identifier,
+            now.format("%Y-%m-%dT%H:%M:%SZ"),
+            manifest,
+            spine
+        )
+        .as_bytes(),
+    )?;
+    zip.finish()?;
+    Ok(())
+}
+
+/// Write `entries` to `path`, along with their notes and highlights.
+pub fn export(
+    entries: &[&EntryWithAuthor],
+    entry_states: &EntryStates,
+    format: Format,
+    path: &Path,
+) -> Result<(), io::Error> {
+    match format {
+        Format::Markdown => {
+            std::fs::write(path, to_markdown(entries, entry_states))
+        }
+        Format::Html => std::fs::write(path, to_html(entries, entry_states)),
+        Format::Epub => {
+            let file = std::fs::File::create(path)?;
+            write_epub(entries, entry_states, file).map_err(io::Error::other)
+        }
+    }
+}
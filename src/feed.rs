@@ -1,44 +1,97 @@
-use feed_rs::{model::Feed, parser};
+use crate::cache::{self, Bodies};
+use crate::filter;
+use crate::storage::{self, Migration};
+use chrono::{DateTime, Utc};
+use feed_rs::model::{Entry, Feed};
+use feed_rs::parser;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::io;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct CachedFeed {
     pub url: String,
     pub feed: Feed,
+    /// Bodies of the entries left on disk when the cache was loaded, for
+    /// which the entry content and summary are empty.
+    #[serde(skip)]
+    pub bodies: Bodies,
+    /// When each entry was first fetched, keyed by entry id.
+    #[serde(default)]
+    pub first_seen: HashMap<String, DateTime<Utc>>,
+}
+
+impl CachedFeed {
+    /// The date of one of the entries of this feed, see `entry_date`.
+    pub fn entry_date(&self, entry: &Entry) -> Option<DateTime<Utc>> {
+        entry_date(entry, self.first_seen.get(&entry.id).copied())
+    }
+}
+
+/// The date an entry was published, falling back to when it was last updated
+/// and then to when it was first fetched.
+pub fn entry_date(
+    entry: &Entry,
+    first_seen: Option<DateTime<Utc>>,
+) -> Option<DateTime<Utc>> {
+    entry.published.or(entry.updated).or(first_seen)
+}
+
+/// Feeds sent to the UI from background tasks.
+pub enum FeedUpdate {
+    /// The feeds loaded from the cache at startup, before any are fetched.
+    Cached(Result<Vec<CachedFeed>, io::Error>),
+    /// Feeds fetched from the network, to be merged into the cached feeds.
+    Fetched(Vec<CachedFeed>),
+    /// The feed URL and id of each entry pruned from the cache, to be
+    /// removed from the current feeds.
+    Pruned(Vec<(String, String)>),
+}
+
+/// A feed listed in the feeds file, along with the tags given after its URL.
+#[derive(Clone)]
+pub struct Subscription {
+    pub url: String,
+    pub tags: Vec<String>,
 }
 
 pub async fn get(
-    feed_path: &str,
-) -> Result<(Vec<Feed>, Vec<String>), std::io::Error> {
-    let urls = get_urls(feed_path)?;
+    subscriptions: Vec<Subscription>,
+) -> Result<Vec<CachedFeed>, std::io::Error> {
     let mut tasks = vec![];
-    for url in urls.clone() {
-        tasks.push(tokio::spawn(fetch_feed(url.clone())));
+    for subscription in &subscriptions {
+        tasks.push(tokio::spawn(fetch_feed(subscription.url.clone())));
     }
     let mut feeds = vec![];
-    let mut successful_urls = vec![];
-    for (task, url) in tasks.into_iter().zip(urls.iter()) {
+    for (task, subscription) in tasks.into_iter().zip(subscriptions) {
         if let Ok(Ok(feed)) = task.await {
-            feeds.push(feed);
-            successful_urls.push(url.clone());
+            feeds.push(CachedFeed {
+                url: subscription.url,
+                feed,
+                bodies: Bodies::default(),
+                first_seen: HashMap::new(),
+            });
         }
     }
-    Ok((feeds, successful_urls))
+    Ok(feeds)
 }
 
-fn get_urls(feed_path: &str) -> Result<Vec<String>, std::io::Error> {
-    match std::fs::read_to_string(feed_path) {
-        Ok(content) => {
-            let feed_urls = content
-                .lines()
-                .map(|line| line.trim().to_string())
-                .filter(|line| !line.is_empty())
-                .collect();
-            Ok(feed_urls)
-        }
-        Err(e) => Err(e),
-    }
+/// Read the feeds file, with one feed per line: a URL followed by any tags,
+/// separated by whitespace. Tags may be quoted to include whitespace.
+pub fn get_subscriptions(
+    feed_path: &str,
+) -> Result<Vec<Subscription>, std::io::Error> {
+    let content = std::fs::read_to_string(feed_path)?;
+    let subscriptions = content
+        .lines()
+        .filter_map(|line| {
+            let mut words = filter::split_words(line).into_iter();
+            let url = words.next()?;
+            let tags = words.collect();
+            Some(Subscription { url, tags })
+        })
+        .collect();
+    Ok(subscriptions)
 }
 
 async fn fetch_feed(url: String) -> Result<Feed, std::io::Error> {
@@ -52,43 +105,124 @@ async fn fetch_feed(url: String) -> Result<Feed, std::io::Error> {
 }
 
 const CACHE_PREFIX: &str = "com.benmandrew.remy";
-const CACHE_FILENAME: &str = "feed_cache.json";
+const CACHE_FILENAME: &str = "feed_cache.bin";
+/// The JSON cache written before version 3.
+const LEGACY_CACHE_FILENAME: &str = "feed_cache.json";
 
-fn get_cache_path() -> Result<std::path::PathBuf, std::io::Error> {
+pub fn get_cache_path() -> Result<std::path::PathBuf, std::io::Error> {
     let xdg_dirs = xdg::BaseDirectories::with_prefix(CACHE_PREFIX);
     xdg_dirs.find_cache_file(CACHE_FILENAME).ok_or_else(|| {
         io::Error::new(io::ErrorKind::NotFound, "cache not found")
     })
 }
 
-pub async fn load_cached_feeds() -> Result<Vec<Feed>, std::io::Error> {
-    let cache_path = get_cache_path()?;
-    let content = tokio::fs::read_to_string(cache_path).await?;
-    let cached: Vec<CachedFeed> = serde_json::from_str(&content)
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-    Ok(cached.into_iter().map(|c| c.feed).collect())
+/// Version of the on-disk cache format, bumped whenever the serialization of
+/// `CachedFeed` or feed-rs's `Feed` changes. Versions 1 and 2 are JSON,
+/// later versions are the binary format of `cache`, which also reads version
+/// 3 caches, written before first-seen times were stored.
+const CACHE_VERSION: u32 = 4;
+
+const LEGACY_CACHE_VERSION: u32 = 2;
+
+/// Version 1 is the unversioned format, whose data is unchanged in version 2.
+const LEGACY_CACHE_MIGRATIONS: &[Migration] = &[Ok];
+
+/// Convert a JSON cache to the binary format, keeping the JSON file as a
+/// backup, or only read it if `read_only`.
+fn migrate_legacy_cache(
+    cache_path: &std::path::Path,
+    read_only: bool,
+) -> Result<Vec<CachedFeed>, std::io::Error> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix(CACHE_PREFIX);
+    let legacy_path = xdg_dirs
+        .find_cache_file(LEGACY_CACHE_FILENAME)
+        .ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "cache not found")
+        })?;
+    let feeds: Vec<CachedFeed> = storage::load(
+        &legacy_path,
+        LEGACY_CACHE_VERSION,
+        LEGACY_CACHE_MIGRATIONS,
+        read_only,
+    )?;
+    if read_only {
+        return Ok(feeds);
+    }
+    cache::write(cache_path, CACHE_VERSION, &feeds)?;
+    let backup = legacy_path
+        .with_extension(format!("json.v{}.bak", LEGACY_CACHE_VERSION));
+    std::fs::rename(&legacy_path, &backup)?;
+    log::info!(
+        "Migrated {} to version {}, backup at {}",
+        legacy_path.display(),
+        CACHE_VERSION,
+        backup.display()
+    );
+    Ok(feeds)
+}
+
+/// Load the cached feeds, leaving entry bodies on disk until they are
+/// needed. A cache which cannot be read is moved aside so that it is not
+/// overwritten by the next save, unless `read_only`, in which case nothing
+/// is written.
+pub async fn load_cached_feeds(
+    read_only: bool,
+) -> Result<Vec<CachedFeed>, std::io::Error> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix(CACHE_PREFIX);
+    let cache_path = match read_only {
+        true => xdg_dirs.get_cache_file(CACHE_FILENAME).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "cache not found")
+        })?,
+        false => xdg_dirs
+            .place_cache_file(CACHE_FILENAME)
+            .map_err(io::Error::other)?,
+    };
+    tokio::task::spawn_blocking(move || {
+        match cache::read(&cache_path, CACHE_VERSION) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                migrate_legacy_cache(&cache_path, read_only)
+            }
+            Err(e)
+                if !read_only
+                    && (e.kind() == io::ErrorKind::InvalidData
+                        || e.kind() == io::ErrorKind::UnexpectedEof) =>
+            {
+                let backup = cache_path.with_extension("bin.corrupt");
+                std::fs::rename(&cache_path, &backup)?;
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{}: {} (moved to {})",
+                        cache_path.display(),
+                        e,
+                        backup.display()
+                    ),
+                ))
+            }
+            // Left in place for the newer version which wrote it
+            Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+                Err(io::Error::new(
+                    e.kind(),
+                    format!("{}: {}", cache_path.display(), e),
+                ))
+            }
+            result => result,
+        }
+    })
+    .await
+    .map_err(io::Error::other)?
 }
 
 pub async fn save_cached_feeds(
-    feeds: &[Feed],
-    urls: &[String],
+    feeds: Vec<CachedFeed>,
 ) -> Result<(), std::io::Error> {
     let xdg_dirs = xdg::BaseDirectories::with_prefix(CACHE_PREFIX);
     let cache_path = xdg_dirs
         .place_cache_file(CACHE_FILENAME)
         .map_err(io::Error::other)?;
-    let cached: Vec<CachedFeed> = feeds
-        .iter()
-        .zip(urls.iter())
-        .map(|(feed, url)| CachedFeed {
-            url: url.clone(),
-            feed: feed.clone(),
-        })
-        .collect();
-    let json = serde_json::to_string_pretty(&cached)
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-    let temp_path = cache_path.with_extension("tmp");
-    tokio::fs::write(&temp_path, json).await?;
-    tokio::fs::rename(temp_path, cache_path).await?;
-    Ok(())
+    tokio::task::spawn_blocking(move || {
+        cache::write(&cache_path, CACHE_VERSION, &feeds)
+    })
+    .await
+    .map_err(io::Error::other)?
 }
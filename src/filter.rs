@@ -0,0 +1,112 @@
+use crate::state::EntryWithAuthor;
+use chrono::{DateTime, NaiveDate, Utc};
+
+/// A term of a filter query. Text is matched case-insensitively as a
+/// substring.
+enum Term {
+    /// Matches the title, author or feed name.
+    Any(String),
+    Author(String),
+    /// Matches the feed title or URL.
+    Feed(String),
+    Tag(String),
+    /// Entries dated before the start of this day.
+    Before(DateTime<Utc>),
+    /// Entries dated on or after the start of this day.
+    After(DateTime<Utc>),
+}
+
+/// The feed an entry belongs to, as far as filtering is concerned.
+pub struct FeedInfo<'a> {
+    pub title: &'a str,
+    pub tags: &'a [String],
+}
+
+/// A query typed after `/`, made of whitespace-separated terms which must
+/// all match. Terms may be prefixed with `author:`, `feed:`, `tag:`,
+/// `before:` or `after:`, and quoted to include spaces.
+#[derive(Default)]
+pub struct Query {
+    terms: Vec<Term>,
+}
+
+/// Split a query, or a line of the feeds file, on whitespace outside of
+/// double quotes.
+pub fn split_words(query: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut quoted = false;
+    for c in query.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+fn parse_day(day: &str) -> Option<DateTime<Utc>> {
+    let day = NaiveDate::parse_from_str(day, "%Y-%m-%d").ok()?;
+    Some(day.and_hms_opt(0, 0, 0)?.and_utc())
+}
+
+impl Query {
+    /// Parse a query. Empty terms and dates which are not in the form
+    /// `YYYY-MM-DD`, such as those still being typed, are ignored.
+    pub fn parse(query: &str) -> Self {
+        let terms = split_words(query)
+            .into_iter()
+            .filter_map(|word| {
+                let Some((prefix, value)) = word.split_once(':') else {
+                    return Some(Term::Any(word.to_lowercase()));
+                };
+                let value = value.to_lowercase();
+                if value.is_empty() {
+                    return None;
+                }
+                match prefix {
+                    "author" => Some(Term::Author(value)),
+                    "feed" => Some(Term::Feed(value)),
+                    "tag" => Some(Term::Tag(value)),
+                    "before" => parse_day(&value).map(Term::Before),
+                    "after" => parse_day(&value).map(Term::After),
+                    _ => Some(Term::Any(word.to_lowercase())),
+                }
+            })
+            .collect();
+        Query { terms }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    pub fn matches(&self, entry: &EntryWithAuthor, feed: &FeedInfo) -> bool {
+        let contains =
+            |text: &str, value: &str| text.to_lowercase().contains(value);
+        self.terms.iter().all(|term| match term {
+            Term::Any(value) => {
+                contains(entry.title(), value)
+                    || contains(&entry.author, value)
+                    || contains(feed.title, value)
+            }
+            Term::Author(value) => contains(&entry.author, value),
+            Term::Feed(value) => {
+                contains(feed.title, value) || contains(&entry.feed_url, value)
+            }
+            Term::Tag(value) => {
+                feed.tags.iter().any(|tag| tag.to_lowercase() == *value)
+            }
+            Term::Before(day) => entry.date().is_some_and(|d| d < *day),
+            Term::After(day) => entry.date().is_some_and(|d| d >= *day),
+        })
+    }
+}
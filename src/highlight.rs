@@ -0,0 +1,209 @@
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{FontStyle, ThemeSet};
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+const THEME: &str = "base16-ocean.dark";
+
+/// Highlighted blocks are kept, as entries are rendered again on every frame,
+/// up to this many.
+const MAX_CACHED_BLOCKS: usize = 64;
+
+static SYNTAXES: LazyLock<SyntaxSet> =
+    LazyLock::new(SyntaxSet::load_defaults_newlines);
+
+static THEMES: LazyLock<ThemeSet> = LazyLock::new(ThemeSet::load_defaults);
+
+type CacheKey = (Option<String>, String, usize);
+
+static CACHE: LazyLock<Mutex<HashMap<CacheKey, Vec<Line<'static>>>>> =
+    LazyLock::new(Mutex::default);
+
+/// The language named by a class of a code block's element, such as
+/// `language-rust`, `lang-rust` or `highlight-rust`.
+pub fn language_from_class(class: &str) -> Option<String> {
+    class.split_whitespace().find_map(|class| {
+        ["language-", "lang-", "highlight-source-", "highlight-"]
+            .iter()
+            .find_map(|prefix| class.strip_prefix(prefix))
+            .filter(|language| !language.is_empty())
+            .map(str::to_string)
+    })
+}
+
+/// The lines of a block of code, highlighted as `language` if it is known
+/// or else as the language it looks like, on a background padded to `width`
+/// columns, or to the longest line if it is zero.
+pub fn highlight_block(
+    code: &str,
+    language: Option<&str>,
+    width: usize,
+) -> Vec<Line<'static>> {
+    let key = (language.map(str::to_string), code.to_string(), width);
+    if let Some(lines) = CACHE.lock().unwrap().get(&key) {
+        return lines.clone();
+    }
+    let lines = highlight(code, language, width);
+    let mut cache = CACHE.lock().unwrap();
+    if cache.len() >= MAX_CACHED_BLOCKS {
+        cache.clear();
+    }
+    cache.insert(key, lines.clone());
+    lines
+}
+
+fn highlight(
+    code: &str,
+    language: Option<&str>,
+    width: usize,
+) -> Vec<Line<'static>> {
+    let theme = &THEMES.themes[THEME];
+    let background = theme
+        .settings
+        .background
+        .map_or(Color::Reset, |c| Color::Rgb(c.r, c.g, c.b));
+    let code = code.replace('\t', "    ");
+    let code = code.trim_end_matches('\n');
+    let syntax = syntax(code, language);
+    let mut highlighter = HighlightLines::new(syntax, theme);
+    let mut lines = vec![];
+    for text in LinesWithEndings::from(code) {
+        let mut spans = vec![Span::raw(" ")];
+        match highlighter.highlight_line(text, &SYNTAXES) {
+            Ok(ranges) => {
+                for (style, piece) in ranges {
+                    let piece = piece.trim_end_matches(['\n', '\r']);
+                    if !piece.is_empty() {
+                        spans.push(Span::styled(
+                            piece.to_string(),
+                            convert_style(style),
+                        ));
+                    }
+                }
+            }
+            Err(_) => spans.push(Span::raw(
+                text.trim_end_matches(['\n', '\r']).to_string(),
+            )),
+        }
+        lines.push(Line::from(spans));
+    }
+    // Lines are padded so that the background makes a rectangle around the
+    // block, with a column to spare on either side. Lines too long for the
+    // pane are left to wrap, and the rest are padded to its width rather
+    // than theirs, which would wrap onto rows of blank background.
+    let width = match width {
+        0 => lines.iter().map(Line::width).max().unwrap_or(0) + 1,
+        width => width,
+    };
+    lines
+        .into_iter()
+        .map(|mut line| {
+            let padding = width.saturating_sub(line.width());
+            // Lines of only spaces are wrapped onto two when the pane's
+            // lines are wrapped, unlike those of non-breaking spaces
+            let space = match line.width() {
+                1 => "\u{a0}",
+                _ => " ",
+            };
+            line.spans.push(Span::raw(space.repeat(padding)));
+            for span in &mut line.spans {
+                span.style = span.style.bg(background);
+            }
+            line
+        })
+        .collect()
+}
+
+fn convert_style(style: syntect::highlighting::Style) -> Style {
+    let fg = style.foreground;
+    let mut converted = Style::new().fg(Color::Rgb(fg.r, fg.g, fg.b));
+    if style.font_style.contains(FontStyle::BOLD) {
+        converted = converted.add_modifier(Modifier::BOLD);
+    }
+    if style.font_style.contains(FontStyle::ITALIC) {
+        converted = converted.add_modifier(Modifier::ITALIC);
+    }
+    if style.font_style.contains(FontStyle::UNDERLINE) {
+        converted = converted.add_modifier(Modifier::UNDERLINED);
+    }
+    converted
+}
+
+/// The syntax of `language`, or of the code's first line, such as a shebang,
+/// or failing that a guess from keywords common in the code.
+fn syntax(code: &str, language: Option<&str>) -> &'static SyntaxReference {
+    let by_token = |token: &str| {
+        SYNTAXES.find_syntax_by_token(token).or_else(|| {
+            ALIASES
+                .iter()
+                .find(|(alias, _)| alias.eq_ignore_ascii_case(token))
+                .and_then(|(_, token)| SYNTAXES.find_syntax_by_token(token))
+        })
+    };
+    language
+        .and_then(by_token)
+        .or_else(|| {
+            let first_line = code.lines().next().unwrap_or("");
+            SYNTAXES.find_syntax_by_first_line(first_line)
+        })
+        .or_else(|| guess(code).and_then(by_token))
+        .unwrap_or_else(|| SYNTAXES.find_syntax_plain_text())
+}
+
+/// Names used in class attributes which are not those of a syntax or one
+/// of its extensions.
+const ALIASES: &[(&str, &str)] = &[
+    ("shell", "sh"),
+    ("console", "sh"),
+    ("zsh", "sh"),
+    ("golang", "go"),
+    ("c++", "cpp"),
+    ("csharp", "cs"),
+    ("objc", "m"),
+    ("typescript", "js"),
+    ("ts", "js"),
+    ("jsx", "js"),
+    ("tsx", "js"),
+    ("toml", "ini"),
+    ("dockerfile", "sh"),
+    ("text", "txt"),
+    ("plaintext", "txt"),
+];
+
+/// The language of a snippet, by the keywords and punctuation it uses.
+fn guess(code: &str) -> Option<&'static str> {
+    let has = |patterns: &[&str]| patterns.iter().any(|p| code.contains(p));
+    let starts = |patterns: &[&str]| {
+        code.lines().any(|line| {
+            let line = line.trim_start();
+            patterns.iter().any(|p| line.starts_with(p))
+        })
+    };
+    if has(&["fn ", "let mut ", "impl ", "pub struct ", "::<", "#[derive"]) {
+        Some("rs")
+    } else if has(&["func ", "package main", ":= "]) {
+        Some("go")
+    } else if starts(&["def ", "import ", "from "]) && has(&[":\n", "):"]) {
+        Some("py")
+    } else if has(&["#include", "std::", "int main("]) {
+        Some("cpp")
+    } else if has(&["function ", "const ", "=> ", "console.log"]) {
+        Some("js")
+    } else if has(&["public class ", "public static void "]) {
+        Some("java")
+    } else if starts(&["$ ", "sudo ", "cd ", "git ", "cargo ", "npm "]) {
+        Some("sh")
+    } else if starts(&["SELECT ", "select ", "CREATE TABLE", "INSERT INTO"]) {
+        Some("sql")
+    } else if starts(&["{", "["]) && has(&["\":"]) {
+        Some("json")
+    } else if starts(&["<"]) && has(&["</", "/>"]) {
+        Some("html")
+    } else {
+        None
+    }
+}
@@ -0,0 +1,649 @@
+use crate::config::ImageMode;
+use crate::storage;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use image::RgbaImage;
+use image::imageops::FilterType;
+use ratatui::buffer::Buffer;
+use ratatui::layout::Rect;
+use ratatui::style::Color;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const CACHE_PREFIX: &str = "com.benmandrew.remy";
+const CACHE_DIR: &str = "images";
+
+/// Images are scaled down to fit in this many rows.
+const MAX_IMAGE_ROWS: u16 = 20;
+
+/// Assumed size of a cell in pixels when the terminal does not report it.
+const DEFAULT_CELL_SIZE: (u16, u16) = (8, 16);
+
+/// Scaled and encoded images kept before the caches are emptied.
+const MAX_CACHED: usize = 32;
+
+/// Fetched images kept decoded before the oldest are dropped, to be loaded
+/// from the disk cache again when next shown.
+const MAX_LOADED: usize = 64;
+
+/// Images are not downloaded if they are larger than this.
+const MAX_IMAGE_BYTES: usize = 20 * 1024 * 1024;
+
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A way of drawing images in the terminal.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Protocol {
+    Kitty,
+    Sixel,
+    Iterm2,
+    /// Coloured Unicode half blocks, two pixels to a cell.
+    Halfblocks,
+}
+
+impl Protocol {
+    /// Guess the protocol the terminal supports from its environment.
+    fn detect() -> Protocol {
+        let var = |name| std::env::var(name).unwrap_or_default();
+        let term = var("TERM");
+        let program = var("TERM_PROGRAM");
+        if std::env::var_os("KITTY_WINDOW_ID").is_some()
+            || term == "xterm-kitty"
+            || program == "ghostty"
+        {
+            Protocol::Kitty
+        } else if program == "iTerm.app" || program == "WezTerm" {
+            Protocol::Iterm2
+        } else if term.contains("sixel")
+            || term.starts_with("foot")
+            || term.starts_with("mlterm")
+            || term == "yaft-256color"
+        {
+            Protocol::Sixel
+        } else {
+            Protocol::Halfblocks
+        }
+    }
+}
+
+enum Fetch {
+    Loading,
+    Ready(Arc<RgbaImage>),
+    Failed,
+}
+
+/// The images fetched so far, of which only the most recently loaded are
+/// kept decoded.
+#[derive(Default)]
+struct Fetched {
+    images: HashMap<String, Fetch>,
+    /// URLs of the images which are ready, oldest first.
+    ready: VecDeque<String>,
+}
+
+impl Fetched {
+    fn insert(&mut self, url: String, fetch: Fetch) {
+        if let Fetch::Ready(_) = fetch {
+            if self.ready.len() >= MAX_LOADED
+                && let Some(oldest) = self.ready.pop_front()
+            {
+                self.images.remove(&oldest);
+            }
+            self.ready.push_back(url.clone());
+        }
+        self.images.insert(url, fetch);
+    }
+}
+
+/// An image scaled to the cells it is shown in, ready to be drawn with
+/// escape sequences.
+enum Encoded {
+    /// Transmitted to kitty once under `id`, and then placed by `flush` with
+    /// the rows to show. The transmission is kept until it has been sent.
+    Kitty { id: u32, transmit: Option<String> },
+    /// An escape sequence drawing each row of cells of the image, so that
+    /// any of them can be shown without encoding it again.
+    Rows(Vec<String>),
+}
+
+/// The URL and the columns and rows an image is shown in.
+type ImageKey = (String, u16, u16);
+
+/// Rows `first_row` onwards of an image drawn with escape sequences rather
+/// than characters, over `area`.
+#[derive(Clone, PartialEq)]
+struct Graphic {
+    area: Rect,
+    key: ImageKey,
+    first_row: u16,
+}
+
+/// The images of entries, fetched in the background as they are shown.
+pub struct Images {
+    /// How images are drawn, or None when they are turned off.
+    protocol: Option<Protocol>,
+    /// Size of a cell of the terminal in pixels.
+    cell_size: (u16, u16),
+    /// Set when another instance holds the data directory lock, in which
+    /// case fetched images are not cached on disk.
+    pub read_only: bool,
+    fetched: Arc<Mutex<Fetched>>,
+    /// Images scaled to the number of columns and rows they are shown in.
+    scaled: HashMap<ImageKey, RgbaImage>,
+    encoded: HashMap<ImageKey, Encoded>,
+    next_kitty_id: u32,
+    /// Images transmitted to kitty which are no longer cached, to be
+    /// deleted by the next `flush`.
+    freed: Vec<u32>,
+    /// Graphics to draw once the current frame has been, and those already
+    /// on screen.
+    pending: Vec<Graphic>,
+    drawn: Vec<Graphic>,
+}
+
+impl Images {
+    pub fn new(mode: ImageMode) -> Self {
+        let protocol = match mode {
+            ImageMode::Auto => Some(Protocol::detect()),
+            ImageMode::Kitty => Some(Protocol::Kitty),
+            ImageMode::Sixel => Some(Protocol::Sixel),
+            ImageMode::Iterm2 => Some(Protocol::Iterm2),
+            ImageMode::Halfblocks => Some(Protocol::Halfblocks),
+            ImageMode::Off => None,
+        };
+        let cell_size = crossterm::terminal::window_size()
+            .ok()
+            .filter(|size| size.width > 0 && size.height > 0)
+            .filter(|size| size.columns > 0 && size.rows > 0)
+            .map_or(DEFAULT_CELL_SIZE, |size| {
+                (size.width / size.columns, size.height / size.rows)
+            });
+        Images {
+            protocol,
+            cell_size,
+            read_only: false,
+            fetched: Arc::default(),
+            scaled: HashMap::new(),
+            encoded: HashMap::new(),
+            next_kitty_id: 1,
+            freed: vec![],
+            pending: vec![],
+            drawn: vec![],
+        }
+    }
+
+    /// The size of a cell in pixels, which is at least one by one.
+    fn cell_pixels(&self) -> (u32, u32) {
+        (
+            self.cell_size.0.max(1) as u32,
+            self.cell_size.1.max(1) as u32,
+        )
+    }
+
+    /// The columns and rows the image at `url` takes up when shown at most
+    /// `width` columns wide, or None if it cannot be shown yet. Images are
+    /// fetched the first time they are asked for.
+    pub fn size(&self, url: &str, width: u16) -> Option<(u16, u16)> {
+        self.protocol?;
+        let mut fetched = self.fetched.lock().ok()?;
+        let image = match fetched.images.get(url) {
+            Some(Fetch::Ready(image)) => image.clone(),
+            Some(_) => return None,
+            None => {
+                fetched.insert(url.to_string(), Fetch::Loading);
+                let (cell_width, cell_height) = self.cell_pixels();
+                let max_size = (
+                    width.max(1) as u32 * cell_width,
+                    MAX_IMAGE_ROWS as u32 * cell_height,
+                );
+                spawn_fetch(
+                    url.to_string(),
+                    max_size,
+                    self.read_only,
+                    self.fetched.clone(),
+                );
+                return None;
+            }
+        };
+        let (cell_width, cell_height) = self.cell_pixels();
+        let (cell_width, cell_height) = (cell_width as f64, cell_height as f64);
+        let (image_width, image_height) =
+            (image.width().max(1) as f64, image.height().max(1) as f64);
+        let scale = (width.max(1) as f64 * cell_width / image_width)
+            .min(MAX_IMAGE_ROWS as f64 * cell_height / image_height)
+            .min(1.0);
+        let columns = (image_width * scale / cell_width).round().max(1.0);
+        let rows = (image_height * scale / cell_height).round().max(1.0);
+        Some((columns as u16, rows as u16))
+    }
+
+    /// Forget the graphics drawn so far, for when the screen was cleared.
+    pub fn redraw(&mut self) {
+        self.drawn.clear();
+    }
+
+    /// Start a new frame, in which no graphics are drawn until `draw` is
+    /// called.
+    pub fn start_frame(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Draw rows `first_row` onwards of the image at `url`, which is shown
+    /// in `columns` by `rows` cells, in `area`. Half blocks are drawn into
+    /// the buffer, while other protocols leave their cells to be drawn over
+    /// by `flush`, unless something is drawn on top of the image.
+    pub fn draw(
+        &mut self,
+        url: &str,
+        (columns, rows): (u16, u16),
+        first_row: u16,
+        area: Rect,
+        covered: bool,
+        buf: &mut Buffer,
+    ) {
+        let Some(protocol) = self.protocol else {
+            return;
+        };
+        if area.is_empty() || (covered && protocol != Protocol::Halfblocks) {
+            return;
+        }
+        let pixels = match protocol {
+            Protocol::Halfblocks => (columns as u32, rows as u32 * 2),
+            _ => {
+                let (cell_width, cell_height) = self.cell_pixels();
+                (columns as u32 * cell_width, rows as u32 * cell_height)
+            }
+        };
+        let key = (url.to_string(), columns, rows);
+        if !self.scale(&key, pixels) {
+            return;
+        }
+        if protocol == Protocol::Halfblocks {
+            draw_halfblocks(&self.scaled[&key], first_row, area, buf);
+            return;
+        }
+        // Images are encoded whole, so are left out until the pane is wide
+        // enough for them
+        if area.width < columns {
+            return;
+        }
+        if !self.encoded.contains_key(&key) && !self.encode(protocol, &key) {
+            return;
+        }
+        for y in area.top()..area.bottom() {
+            for x in area.left()..area.right() {
+                if let Some(cell) = buf.cell_mut((x, y)) {
+                    cell.set_skip(true);
+                }
+            }
+        }
+        self.pending.push(Graphic {
+            area,
+            key,
+            first_row,
+        });
+    }
+
+    /// Scale the image at `key` to `pixels`, returning false if it has not
+    /// been fetched.
+    fn scale(&mut self, key: &ImageKey, (width, height): (u32, u32)) -> bool {
+        if !self.scaled.contains_key(key) {
+            let image = match self.fetched.lock().as_deref() {
+                Ok(fetched) => match fetched.images.get(&key.0) {
+                    Some(Fetch::Ready(image)) => image.clone(),
+                    _ => return false,
+                },
+                Err(_) => return false,
+            };
+            let image = image::imageops::resize(
+                image.as_ref(),
+                width,
+                height,
+                FilterType::Triangle,
+            );
+            if self.scaled.len() >= MAX_CACHED {
+                self.scaled.clear();
+            }
+            self.scaled.insert(key.clone(), image);
+        }
+        true
+    }
+
+    /// Encode the scaled image at `key`, returning false if it failed.
+    fn encode(&mut self, protocol: Protocol, key: &ImageKey) -> bool {
+        let image = &self.scaled[key];
+        let encoded: Result<Encoded, Box<dyn std::error::Error>> =
+            match protocol {
+                Protocol::Kitty => {
+                    let id = self.next_kitty_id;
+                    png(image).map_err(Into::into).map(|png| Encoded::Kitty {
+                        id,
+                        transmit: Some(kitty_transmit(id, &png)),
+                    })
+                }
+                _ => encode_rows(protocol, image, key.1, key.2)
+                    .map(Encoded::Rows),
+            };
+        let encoded = match encoded {
+            Ok(encoded) => encoded,
+            Err(e) => {
+                log::error!("Failed to encode image {}: {}", key.0, e);
+                return false;
+            }
+        };
+        if let Encoded::Kitty { .. } = encoded {
+            self.next_kitty_id = self.next_kitty_id.wrapping_add(1).max(1);
+        }
+        if self.encoded.len() >= MAX_CACHED {
+            for (_, encoded) in self.encoded.drain() {
+                if let Encoded::Kitty { id, transmit: None } = encoded {
+                    self.freed.push(id);
+                }
+            }
+        }
+        self.encoded.insert(key.clone(), encoded);
+        true
+    }
+
+    /// Draw the graphics of the frame just drawn, unless they are already on
+    /// screen. Kitty keeps images apart from the text, so those of the last
+    /// frame are deleted first, keeping their data to be placed again.
+    pub fn flush(&mut self, out: &mut impl Write) -> io::Result<()> {
+        for id in self.freed.drain(..) {
+            write!(out, "\x1b_Ga=d,d=I,i={},q=2\x1b\\", id)?;
+        }
+        if self.pending == self.drawn {
+            return out.flush();
+        }
+        if self.protocol == Some(Protocol::Kitty) {
+            write!(out, "\x1b_Ga=d,d=a,q=2\x1b\\")?;
+        }
+        let (cell_width, cell_height) = self.cell_pixels();
+        for graphic in &self.pending {
+            let Graphic {
+                area,
+                key,
+                first_row,
+            } = graphic;
+            match self.encoded.get_mut(key) {
+                Some(Encoded::Kitty { id, transmit }) => {
+                    if let Some(transmit) = transmit.take() {
+                        write!(out, "{}", transmit)?;
+                    }
+                    crossterm::queue!(
+                        out,
+                        crossterm::cursor::MoveTo(area.x, area.y)
+                    )?;
+                    write!(
+                        out,
+                        "\x1b_Ga=p,i={},q=2,C=1,x=0,y={},w={},h={},\
+                         c={},r={}\x1b\\",
+                        id,
+                        *first_row as u32 * cell_height,
+                        key.1 as u32 * cell_width,
+                        area.height as u32 * cell_height,
+                        key.1,
+                        area.height,
+                    )?;
+                }
+                Some(Encoded::Rows(rows)) => {
+                    for row in 0..area.height {
+                        let Some(escape) = rows.get((first_row + row) as usize)
+                        else {
+                            break;
+                        };
+                        crossterm::queue!(
+                            out,
+                            crossterm::cursor::MoveTo(area.x, area.y + row)
+                        )?;
+                        write!(out, "{}", escape)?;
+                    }
+                }
+                None => {}
+            }
+        }
+        out.flush()?;
+        self.drawn = self.pending.clone();
+        Ok(())
+    }
+}
+
+/// Draw each cell as the upper half block, coloured with the upper pixel,
+/// on the lower pixel.
+fn draw_halfblocks(
+    image: &RgbaImage,
+    first_row: u16,
+    area: Rect,
+    buf: &mut Buffer,
+) {
+    let color = |x: u32, y: u32| match image.get_pixel_checked(x, y) {
+        Some(pixel) if pixel[3] >= 128 => {
+            Color::Rgb(pixel[0], pixel[1], pixel[2])
+        }
+        _ => Color::Reset,
+    };
+    for row in 0..area.height {
+        let y = (first_row + row) as u32 * 2;
+        for column in 0..area.width {
+            let x = column as u32;
+            if let Some(cell) = buf.cell_mut((area.x + column, area.y + row)) {
+                cell.set_symbol("▀")
+                    .set_fg(color(x, y))
+                    .set_bg(color(x, y + 1));
+            }
+        }
+    }
+}
+
+/// The escape sequence transmitting a PNG to kitty under `id`, in chunks,
+/// without showing it.
+fn kitty_transmit(id: u32, png: &[u8]) -> String {
+    let data = BASE64.encode(png);
+    let chunks: Vec<&[u8]> = data.as_bytes().chunks(4096).collect();
+    let mut escape = String::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = (i + 1 < chunks.len()) as u8;
+        // Base64 is ASCII, so every chunk is valid UTF-8
+        let chunk = String::from_utf8_lossy(chunk);
+        if i == 0 {
+            escape.push_str(&format!(
+                "\x1b_Ga=t,f=100,i={},q=2,m={};{}\x1b\\",
+                id, more, chunk
+            ));
+        } else {
+            escape.push_str(&format!("\x1b_Gm={};{}\x1b\\", more, chunk));
+        }
+    }
+    escape
+}
+
+/// Escape sequences drawing each row of cells of `image`, which is shown in
+/// `columns` by `rows` cells, from the top left cell of the row.
+fn encode_rows(
+    protocol: Protocol,
+    image: &RgbaImage,
+    columns: u16,
+    rows: u16,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let row_height = image.height() / rows.max(1) as u32;
+    (0..rows)
+        .map(|row| {
+            let strip = image::imageops::crop_imm(
+                image,
+                0,
+                row as u32 * row_height,
+                image.width(),
+                row_height,
+            )
+            .to_image();
+            encode_row(protocol, &strip, columns)
+        })
+        .collect()
+}
+
+fn encode_row(
+    protocol: Protocol,
+    strip: &RgbaImage,
+    columns: u16,
+) -> Result<String, Box<dyn std::error::Error>> {
+    match protocol {
+        Protocol::Iterm2 => {
+            let png = png(strip)?;
+            Ok(format!(
+                "\x1b]1337;File=inline=1;size={};width={};height=1;\
+                 preserveAspectRatio=0;doNotMoveCursor=1:{}\x07",
+                png.len(),
+                columns,
+                BASE64.encode(&png)
+            ))
+        }
+        Protocol::Sixel => icy_sixel::sixel_string(
+            strip.as_raw(),
+            strip.width() as i32,
+            strip.height() as i32,
+            icy_sixel::PixelFormat::RGBA8888,
+            icy_sixel::DiffusionMethod::Stucki,
+            icy_sixel::MethodForLargest::Auto,
+            icy_sixel::MethodForRep::Auto,
+            icy_sixel::Quality::HIGH,
+        ),
+        Protocol::Kitty | Protocol::Halfblocks => Ok(String::new()),
+    }
+}
+
+fn png(image: &RgbaImage) -> Result<Vec<u8>, image::ImageError> {
+    let mut png = vec![];
+    image.write_to(&mut io::Cursor::new(&mut png), image::ImageFormat::Png)?;
+    Ok(png)
+}
+
+/// Fetch and decode the image at `url` in the background, from the cache
+/// if it has been fetched before, scaled down to fit in `max_size` pixels.
+fn spawn_fetch(
+    url: String,
+    max_size: (u32, u32),
+    read_only: bool,
+    fetched: Arc<Mutex<Fetched>>,
+) {
+    tokio::spawn(async move {
+        let result = match load(&url, max_size, read_only).await {
+            Ok(image) => Fetch::Ready(Arc::new(image)),
+            Err(e) => {
+                log::error!("Failed to load image {}: {}", url, e);
+                Fetch::Failed
+            }
+        };
+        if let Ok(mut fetched) = fetched.lock() {
+            fetched.insert(url, result);
+        }
+    });
+}
+
+async fn load(
+    url: &str,
+    max_size: (u32, u32),
+    read_only: bool,
+) -> Result<RgbaImage, io::Error> {
+    let path = get_cache_path(url)?;
+    if let Ok(bytes) = tokio::fs::read(&path).await {
+        match decode(bytes, max_size).await {
+            Ok(image) => return Ok(image),
+            Err(e) => {
+                log::warn!("Fetching image {} again: {}", url, e);
+                if !read_only {
+                    let _ = tokio::fs::remove_file(&path).await;
+                }
+            }
+        }
+    }
+    let bytes = download(url).await?;
+    if !read_only && let Err(e) = write_cache(&path, &bytes).await {
+        log::error!("Failed to cache image {}: {}", url, e);
+    }
+    decode(bytes, max_size).await
+}
+
+/// Write a cached image to a temporary file first, so that a partly
+/// written image is never read back.
+async fn write_cache(path: &Path, bytes: &[u8]) -> Result<(), io::Error> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let temporary = storage::temp_path(path);
+    tokio::fs::write(&temporary, bytes).await?;
+    tokio::fs::rename(&temporary, path).await
+}
+
+/// Decode an image, scaling it down to fit in `max_size` pixels so that
+/// large images do not stay in memory at full size.
+async fn decode(
+    bytes: Vec<u8>,
+    (width, height): (u32, u32),
+) -> Result<RgbaImage, io::Error> {
+    tokio::task::spawn_blocking(move || {
+        let image = image::load_from_memory(&bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let image = if image.width() > width || image.height() > height {
+            image.resize(width, height, FilterType::Triangle)
+        } else {
+            image
+        };
+        Ok(image.to_rgba8())
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+async fn download(url: &str) -> Result<Vec<u8>, io::Error> {
+    let client = reqwest::Client::builder()
+        .timeout(DOWNLOAD_TIMEOUT)
+        .build()
+        .map_err(io::Error::other)?;
+    let mut response = client
+        .get(url)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(io::Error::other)?;
+    let too_large = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("larger than {} bytes", MAX_IMAGE_BYTES),
+        )
+    };
+    if response
+        .content_length()
+        .is_some_and(|length| length > MAX_IMAGE_BYTES as u64)
+    {
+        return Err(too_large());
+    }
+    let mut bytes = vec![];
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+    {
+        if bytes.len() + chunk.len() > MAX_IMAGE_BYTES {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+/// Cached images are named by a hash of their URL, which is FNV-1a so that
+/// the names stay the same between builds.
+fn get_cache_path(url: &str) -> Result<std::path::PathBuf, io::Error> {
+    let hash = url.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    let xdg_dirs = xdg::BaseDirectories::with_prefix(CACHE_PREFIX);
+    xdg_dirs
+        .get_cache_file(format!("{}/{:016x}", CACHE_DIR, hash))
+        .ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "no cache directory")
+        })
+}
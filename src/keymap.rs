@@ -0,0 +1,382 @@
+use crate::action::Action;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+
+const CONFIG_PREFIX: &str = "com.benmandrew.remy";
+const KEYMAP_FILENAME: &str = "keymap.toml";
+
+/// Bound in the keymap file in place of an action to remove a binding.
+const UNBIND: &str = "unbind";
+
+/// A key along with the modifiers held when it is pressed.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct KeyChord {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+/// Names of the keys which are not written as the character they type.
+const KEY_NAMES: &[(KeyCode, &str)] = &[
+    (KeyCode::Up, "Up"),
+    (KeyCode::Down, "Down"),
+    (KeyCode::Left, "Left"),
+    (KeyCode::Right, "Right"),
+    (KeyCode::Enter, "Enter"),
+    (KeyCode::Esc, "Esc"),
+    (KeyCode::Tab, "Tab"),
+    (KeyCode::Backspace, "Backspace"),
+    (KeyCode::Delete, "Delete"),
+    (KeyCode::Insert, "Insert"),
+    (KeyCode::Home, "Home"),
+    (KeyCode::End, "End"),
+    (KeyCode::PageUp, "PageUp"),
+    (KeyCode::PageDown, "PageDown"),
+    (KeyCode::Char(' '), "Space"),
+];
+
+const MODIFIER_NAMES: &[(KeyModifiers, &str)] = &[
+    (KeyModifiers::CONTROL, "Ctrl-"),
+    (KeyModifiers::ALT, "Alt-"),
+    (KeyModifiers::SHIFT, "Shift-"),
+];
+
+impl KeyChord {
+    /// The chord of a key press. Shift is dropped from characters, as it is
+    /// already reflected in their case.
+    pub fn from_event(event: KeyEvent) -> Self {
+        let mut modifiers = event.modifiers
+            & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT);
+        if matches!(event.code, KeyCode::Char(_) | KeyCode::BackTab) {
+            modifiers.remove(KeyModifiers::SHIFT);
+        }
+        KeyChord {
+            code: event.code,
+            modifiers,
+        }
+    }
+
+    /// Parse a chord such as `j`, `G`, `ctrl-d`, `PageDown` or `shift-tab`.
+    /// Modifiers and key names are case-insensitive.
+    fn parse(chord: &str) -> Option<Self> {
+        let mut rest = chord;
+        let mut modifiers = KeyModifiers::NONE;
+        'modifiers: loop {
+            for (modifier, name) in MODIFIER_NAMES {
+                if rest.len() > name.len()
+                    && rest
+                        .get(..name.len())
+                        .is_some_and(|p| p.eq_ignore_ascii_case(name))
+                {
+                    modifiers |= *modifier;
+                    rest = &rest[name.len()..];
+                    continue 'modifiers;
+                }
+            }
+            break;
+        }
+        let mut code = if let Some((code, _)) =
+            KEY_NAMES.iter().find(|(_, n)| n.eq_ignore_ascii_case(rest))
+        {
+            *code
+        } else if let Some(n) = rest
+            .strip_prefix(['f', 'F'])
+            .and_then(|n| n.parse::<u8>().ok())
+        {
+            KeyCode::F(n)
+        } else {
+            let mut chars = rest.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => KeyCode::Char(c),
+                _ => return None,
+            }
+        };
+        if modifiers.contains(KeyModifiers::SHIFT) {
+            match code {
+                KeyCode::Tab => code = KeyCode::BackTab,
+                KeyCode::Char(c) => {
+                    code = KeyCode::Char(c.to_ascii_uppercase())
+                }
+                _ => {}
+            }
+            if matches!(code, KeyCode::Char(_) | KeyCode::BackTab) {
+                modifiers.remove(KeyModifiers::SHIFT);
+            }
+        }
+        Some(KeyChord { code, modifiers })
+    }
+}
+
+impl fmt::Display for KeyChord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (modifier, name) in MODIFIER_NAMES {
+            if self.modifiers.contains(*modifier) {
+                write!(f, "{}", name)?;
+            }
+        }
+        match self.code {
+            KeyCode::BackTab => write!(f, "Shift-Tab"),
+            KeyCode::F(n) => write!(f, "F{}", n),
+            code => match KEY_NAMES.iter().find(|(c, _)| *c == code) {
+                Some((_, name)) => write!(f, "{}", name),
+                None => match code {
+                    KeyCode::Char(c) => write!(f, "{}", c),
+                    code => write!(f, "{:?}", code),
+                },
+            },
+        }
+    }
+}
+
+/// Keys pressed one after the other, such as `g g`.
+pub type KeySequence = Vec<KeyChord>;
+
+fn parse_sequence(sequence: &str) -> Option<KeySequence> {
+    let chords: Option<KeySequence> =
+        sequence.split_whitespace().map(KeyChord::parse).collect();
+    chords.filter(|chords| !chords.is_empty())
+}
+
+pub fn format_sequence(sequence: &[KeyChord]) -> String {
+    sequence
+        .iter()
+        .map(KeyChord::to_string)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Where a binding applies. Bindings of the focused pane take precedence
+/// over global ones.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Context {
+    Global,
+    List,
+    Content,
+    Sidebar,
+    /// While the matches of a find are shown in the content pane.
+    Find,
+    /// While lines are being selected for highlighting, in which case no
+    /// other bindings apply.
+    Highlight,
+}
+
+const CONTEXTS: &[Context] = &[
+    Context::Global,
+    Context::List,
+    Context::Content,
+    Context::Sidebar,
+    Context::Find,
+    Context::Highlight,
+];
+
+const DEFAULT_BINDINGS: &[(Context, &str, Action)] = &[
+    (Context::Global, "Up", Action::MoveUp),
+    (Context::Global, "k", Action::MoveUp),
+    (Context::Global, "Down", Action::MoveDown),
+    (Context::Global, "j", Action::MoveDown),
+    (Context::Global, "Left", Action::MoveLeft),
+    (Context::Global, "h", Action::MoveLeft),
+    (Context::Global, "Right", Action::MoveRight),
+    (Context::Global, "l", Action::MoveRight),
+    (Context::Global, "ctrl-d", Action::HalfPageDown),
+    (Context::Global, "ctrl-u", Action::HalfPageUp),
+    (Context::Global, "PageDown", Action::PageDown),
+    (Context::Global, "PageUp", Action::PageUp),
+    (Context::Global, "g g", Action::GoToTop),
+    (Context::Global, "Home", Action::GoToTop),
+    (Context::Global, "G", Action::GoToBottom),
+    (Context::Global, "End", Action::GoToBottom),
+    (Context::Global, "Enter", Action::OpenLink),
+    (Context::Global, "Tab", Action::NextLink),
+    (Context::Global, "shift-Tab", Action::PreviousLink),
+    (Context::Global, "r", Action::ToggleRaw),
+    (Context::Global, "m", Action::ToggleRead),
+    (Context::Global, "M", Action::MarkAllRead),
+    (Context::Global, "s", Action::ToggleStarred),
+    (Context::Global, "S", Action::ToggleStarredView),
+    (Context::Global, "f", Action::ToggleSidebar),
+    (Context::Global, "o", Action::CycleSort),
+    (Context::Global, "O", Action::CycleGrouping),
+    (Context::Global, "/", Action::Filter),
+    (Context::Content, "/", Action::Find),
+    (Context::Find, "n", Action::NextMatch),
+    (Context::Find, "N", Action::PreviousMatch),
+    (Context::Global, "F", Action::Search),
+    (Context::Global, "n", Action::EditNote),
+    (Context::Global, "v", Action::Highlight),
+    (Context::Global, "Esc", Action::Cancel),
+    (Context::Global, "?", Action::ToggleHelp),
+    (Context::Global, ":", Action::CommandLine),
+    (Context::Global, "R", Action::Refresh),
+    (Context::Global, "q", Action::Quit),
+    (Context::Highlight, "Up", Action::MoveUp),
+    (Context::Highlight, "k", Action::MoveUp),
+    (Context::Highlight, "Down", Action::MoveDown),
+    (Context::Highlight, "j", Action::MoveDown),
+    (Context::Highlight, "ctrl-d", Action::HalfPageDown),
+    (Context::Highlight, "ctrl-u", Action::HalfPageUp),
+    (Context::Highlight, "PageDown", Action::PageDown),
+    (Context::Highlight, "PageUp", Action::PageUp),
+    (Context::Highlight, "g g", Action::GoToTop),
+    (Context::Highlight, "Home", Action::GoToTop),
+    (Context::Highlight, "G", Action::GoToBottom),
+    (Context::Highlight, "End", Action::GoToBottom),
+    (Context::Highlight, "v", Action::Highlight),
+    (Context::Highlight, "Enter", Action::Highlight),
+    (Context::Highlight, "Esc", Action::Cancel),
+];
+
+/// The outcome of looking up the keys pressed so far.
+pub enum Lookup {
+    Action(Action),
+    /// The keys are the start of a longer sequence.
+    Pending,
+    None,
+}
+
+/// The actions bound to key sequences in each context. A sequence bound to
+/// `None` has been unbound, hiding any binding in a less specific context.
+pub struct Keymap {
+    bindings: HashMap<Context, HashMap<KeySequence, Option<Action>>>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut bindings: HashMap<_, HashMap<_, _>> = HashMap::new();
+        for (context, sequence, action) in DEFAULT_BINDINGS {
+            let sequence =
+                parse_sequence(sequence).expect("invalid default binding");
+            bindings
+                .entry(*context)
+                .or_default()
+                .insert(sequence, Some(*action));
+        }
+        Keymap { bindings }
+    }
+}
+
+impl Keymap {
+    /// Look up the keys pressed so far in each of `contexts`, most specific
+    /// first.
+    pub fn lookup(&self, contexts: &[Context], keys: &[KeyChord]) -> Lookup {
+        for context in contexts {
+            let Some(bindings) = self.bindings.get(context) else {
+                continue;
+            };
+            match bindings.get(keys) {
+                Some(Some(action)) => return Lookup::Action(*action),
+                Some(None) => return Lookup::None,
+                None => {}
+            }
+        }
+        let is_prefix = contexts
+            .iter()
+            .filter_map(|context| self.bindings.get(context))
+            .flat_map(|bindings| bindings.iter())
+            .any(|(sequence, action)| {
+                action.is_some()
+                    && sequence.len() > keys.len()
+                    && sequence.starts_with(keys)
+            });
+        if is_prefix {
+            Lookup::Pending
+        } else {
+            Lookup::None
+        }
+    }
+
+    /// The key sequences bound to an action in any context.
+    pub fn sequences(&self, action: Action) -> Vec<String> {
+        let mut sequences: Vec<String> = vec![];
+        for context in CONTEXTS {
+            let Some(bindings) = self.bindings.get(context) else {
+                continue;
+            };
+            let mut bound: Vec<String> = bindings
+                .iter()
+                .filter(|(_, a)| **a == Some(action))
+                .map(|(sequence, _)| format_sequence(sequence))
+                .filter(|sequence| !sequences.contains(sequence))
+                .collect();
+            bound.sort_by_key(|s| (s.chars().count() > 1, s.clone()));
+            sequences.extend(bound);
+        }
+        sequences
+    }
+
+    /// The keys of each action with their description, for the help popup.
+    pub fn help(&self) -> Vec<(String, &'static str)> {
+        let mut help: Vec<(String, &'static str)> = vec![];
+        for action in Action::all() {
+            let sequences = self.sequences(action);
+            if sequences.is_empty() {
+                continue;
+            }
+            let keys = sequences.join("/");
+            match help.last_mut() {
+                Some((last_keys, description))
+                    if *description == action.description() =>
+                {
+                    last_keys.push('/');
+                    last_keys.push_str(&keys);
+                }
+                _ => help.push((keys, action.description())),
+            }
+        }
+        help
+    }
+
+    /// Apply the bindings of a keymap file, which has a table for each
+    /// context mapping key sequences to action names, or to `unbind`.
+    fn apply(
+        &mut self,
+        file: HashMap<Context, HashMap<String, String>>,
+    ) -> Result<(), io::Error> {
+        for (context, bindings) in file {
+            for (sequence, name) in bindings {
+                let keys = parse_sequence(&sequence).ok_or_else(|| {
+                    invalid_data(format!("invalid key sequence {:?}", sequence))
+                })?;
+                let action = if name == UNBIND {
+                    None
+                } else {
+                    Some(Action::from_name(&name).ok_or_else(|| {
+                        invalid_data(format!("unknown action {:?}", name))
+                    })?)
+                };
+                let bindings = self.bindings.entry(context).or_default();
+                if action.is_none() && context == Context::Global {
+                    bindings.remove(&keys);
+                } else {
+                    bindings.insert(keys, action);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn get_keymap_path() -> Option<std::path::PathBuf> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix(CONFIG_PREFIX);
+    xdg_dirs.find_config_file(KEYMAP_FILENAME)
+}
+
+/// The default keymap, with the bindings of the keymap file applied.
+pub fn load() -> Result<Keymap, io::Error> {
+    let mut keymap = Keymap::default();
+    let Some(keymap_path) = get_keymap_path() else {
+        return Ok(keymap);
+    };
+    let content = std::fs::read_to_string(keymap_path)?;
+    let file = toml::from_str(&content)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    keymap.apply(file)?;
+    Ok(keymap)
+}
@@ -0,0 +1,34 @@
+use std::fs::{File, TryLockError};
+use std::io::{self, Write};
+
+const DATA_PREFIX: &str = "com.benmandrew.remy";
+const LOCK_FILENAME: &str = "remy.lock";
+
+/// Advisory lock on the data directory, held by the one instance of remy
+/// which may write to the cache and entry state. The lock is released when
+/// this is dropped.
+pub struct DataLock {
+    _file: File,
+}
+
+/// Take the lock, or return `None` if another instance already holds it.
+pub fn acquire() -> Result<Option<DataLock>, io::Error> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix(DATA_PREFIX);
+    let lock_path = xdg_dirs
+        .place_data_file(LOCK_FILENAME)
+        .map_err(io::Error::other)?;
+    let mut file = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(lock_path)?;
+    match file.try_lock() {
+        Ok(()) => {
+            file.set_len(0)?;
+            writeln!(file, "{}", std::process::id())?;
+            Ok(Some(DataLock { _file: file }))
+        }
+        Err(TryLockError::WouldBlock) => Ok(None),
+        Err(TryLockError::Error(e)) => Err(e),
+    }
+}
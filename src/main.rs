@@ -1,13 +1,34 @@
+mod action;
+mod cache;
+mod cli;
+mod config;
+mod entry_state;
+mod export;
 mod feed;
+mod filter;
+mod highlight;
+mod images;
+mod keymap;
+mod lock;
 mod popup;
 mod render;
+mod retention;
+mod search;
 mod state;
+mod storage;
+mod table;
 
-use crossterm::event::{self, Event, KeyCode};
+use clap::Parser;
+use crossterm::event::{self, Event, KeyCode, KeyModifiers};
 use ratatui::DefaultTerminal;
 use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 use tokio::sync::mpsc;
 
+use crate::action::Action;
+use crate::keymap::{Context, KeyChord, Lookup};
 use crate::render::render;
 
 const LOG_PATH: &str = "remy.log";
@@ -29,99 +50,450 @@ fn init_crossterm() {
     .unwrap();
 }
 
-fn handle_key_event(event: event::KeyEvent, state: &mut state::State) -> bool {
-    let mut exit = false;
-    match event.code {
-        KeyCode::Down => state.move_down(),
-        KeyCode::Up => state.move_up(),
-        KeyCode::Left => state.move_left(),
-        KeyCode::Right => state.move_right(),
-        KeyCode::Enter => state.open_selected_entry_link(),
-        KeyCode::Char('r') => state.switch_render_mode(),
-        KeyCode::Char('h') => match state.selected_window {
-            state::SelectedWindow::HelpPopup => {
-                state.selected_window = state::SelectedWindow::EntryList;
-            }
-            _ => {
-                state.selected_window = state::SelectedWindow::HelpPopup;
+/// The action of a key press, if any. While a prompt is open keys edit it,
+/// otherwise they are looked up in the keymap, possibly as part of a
+/// sequence.
+fn key_action(
+    event: event::KeyEvent,
+    state: &mut state::State,
+) -> Option<Action> {
+    if state.is_typing() {
+        return match event.code {
+            KeyCode::Esc => Some(Action::Cancel),
+            KeyCode::Enter => Some(Action::Submit),
+            KeyCode::Backspace => Some(Action::DeleteChar),
+            KeyCode::Down => Some(Action::MoveDown),
+            KeyCode::Up => Some(Action::MoveUp),
+            KeyCode::Char(c) => Some(Action::Type(c)),
+            _ => None,
+        };
+    }
+    let chord = KeyChord::from_event(event);
+    let contexts = key_contexts(state);
+    // Digits which are not bound start or continue a count, except for a
+    // leading zero
+    if let KeyCode::Char(c) = event.code
+        && let Some(digit) = c.to_digit(10)
+        && event.modifiers.difference(KeyModifiers::SHIFT).is_empty()
+        && state.pending_keys.is_empty()
+        && (digit > 0 || state.pending_count.is_some())
+        && matches!(state.keymap.lookup(&contexts, &[chord]), Lookup::None)
+    {
+        return Some(Action::Count(digit));
+    }
+    state.pending_keys.push(chord);
+    let action = match state.keymap.lookup(&contexts, &state.pending_keys) {
+        Lookup::Action(action) => Some(action),
+        Lookup::Pending => return None,
+        // A key which breaks off a sequence is looked up on its own
+        Lookup::None if state.pending_keys.len() > 1 => {
+            state.pending_keys.drain(..state.pending_keys.len() - 1);
+            match state.keymap.lookup(&contexts, &state.pending_keys) {
+                Lookup::Action(action) => Some(action),
+                Lookup::Pending => return None,
+                Lookup::None => None,
             }
-        },
-        KeyCode::Char('q') => exit = true,
-        _ => {}
+        }
+        Lookup::None => None,
     };
-    exit
+    state.pending_keys.clear();
+    if action.is_none() {
+        state.pending_count = None;
+    }
+    action
+}
+
+/// The contexts whose key bindings apply, most specific first.
+fn key_contexts(state: &state::State) -> Vec<Context> {
+    if state.highlighting.is_some() {
+        return vec![Context::Highlight];
+    }
+    let mut contexts = vec![];
+    if state.is_finding() {
+        contexts.push(Context::Find);
+    }
+    match state.selected_window {
+        state::SelectedWindow::EntryList => contexts.push(Context::List),
+        state::SelectedWindow::EntryContent => contexts.push(Context::Content),
+        state::SelectedWindow::Sidebar => contexts.push(Context::Sidebar),
+        _ => {}
+    }
+    contexts.push(Context::Global);
+    contexts
+}
+
+fn mouse_action(event: event::MouseEvent, width: u16) -> Option<Action> {
+    let (column, row) = (event.column, event.row);
+    match event.kind {
+        event::MouseEventKind::Down(event::MouseButton::Left) => {
+            Some(Action::Click { column, row, width })
+        }
+        event::MouseEventKind::Up(event::MouseButton::Left) => {
+            Some(Action::ReleaseDivider)
+        }
+        event::MouseEventKind::Drag(event::MouseButton::Left) => {
+            Some(Action::DragDivider { column, width })
+        }
+        event::MouseEventKind::ScrollDown => Some(Action::Scroll {
+            column,
+            row,
+            delta: 1,
+        }),
+        event::MouseEventKind::ScrollUp => Some(Action::Scroll {
+            column,
+            row,
+            delta: -1,
+        }),
+        _ => None,
+    }
 }
 
-fn handle_mouse_event(
-    event: event::MouseEvent,
-    terminal: &DefaultTerminal,
+/// Bring the search index up to date with the current entries and their
+/// annotations in the background.
+fn spawn_index_update(state: &state::State) {
+    let index = state.search_index.clone();
+    let entries = state.entries.clone();
+    let annotations = state.entry_states.annotations();
+    let read_only = state.read_only;
+    tokio::task::spawn_blocking(move || {
+        let Ok(mut index) = index.lock() else {
+            return;
+        };
+        if index.update(entries.iter(), &annotations)
+            && !read_only
+            && !index.read_only
+            && let Err(e) = search::save(&index)
+        {
+            log::error!("Failed to save search index: {}", e);
+        }
+    });
+}
+
+fn handle_feed_update(
+    update: feed::FeedUpdate,
     state: &mut state::State,
-) -> Result<(), std::io::Error> {
-    log::warn!("Bruh");
-    if event.kind == event::MouseEventKind::Down(event::MouseButton::Left)
-        && state
-            .separator
-            .mouse_on_separator(event.column, terminal.size()?.width)
-    {
-        state.separator.mouse_down = true;
-    } else if event.kind == event::MouseEventKind::Up(event::MouseButton::Left)
-    {
-        state.separator.mouse_down = false;
+    feed_tx: &mpsc::Sender<feed::FeedUpdate>,
+) {
+    match update {
+        feed::FeedUpdate::Cached(result) => {
+            // Added to any warnings from startup rather than replacing them
+            let mut warnings: Vec<String> =
+                state.status.take().into_iter().collect();
+            // Saving would overwrite the cache written by the newer version
+            if let Err(e) = &result
+                && e.kind() == std::io::ErrorKind::Unsupported
+            {
+                state.read_only = true;
+            }
+            let feeds = or_warn(result, "cache", &mut warnings);
+            state.loading = false;
+            state.update_feeds(feeds);
+            if !warnings.is_empty() {
+                state.status = Some(warnings.join("; "));
+            }
+        }
+        feed::FeedUpdate::Fetched(feeds) => {
+            let feeds = retention::merge(&state.feeds, feeds);
+            state.update_feeds(feeds.clone());
+            if state.read_only {
+                return;
+            }
+            spawn_cache_save(feeds.clone());
+            let retention = state.config.retention.clone();
+            let keep = retention::entries_to_keep(
+                &feeds,
+                &state.entry_states,
+                &retention,
+            );
+            let feed_tx = feed_tx.clone();
+            tokio::spawn(async move {
+                let mut feeds = feeds;
+                let removed = retention::prune(&mut feeds, &retention, &keep);
+                if !removed.is_empty() {
+                    log::info!(
+                        "Pruned {} entries from the cache",
+                        removed.len()
+                    );
+                    let update = feed::FeedUpdate::Pruned(removed);
+                    let _ = feed_tx.send(update).await;
+                }
+            });
+        }
+        // Feeds fetched while pruning are kept, with only the entries
+        // pruned removed
+        feed::FeedUpdate::Pruned(removed) => {
+            let mut feeds = state.feeds.clone();
+            retention::remove(&mut feeds, &removed);
+            state.update_feeds(feeds.clone());
+            if !state.read_only {
+                spawn_cache_save(feeds);
+            }
+        }
     }
-    if state.separator.mouse_down
-        && event.kind == event::MouseEventKind::Drag(event::MouseButton::Left)
-    {
-        state
-            .separator
-            .update_position(event.column, terminal.size()?.width);
+}
+
+/// Save the feeds in the background. Saves are made one at a time, and one
+/// requested before the last save made is skipped, so that an older
+/// snapshot of the feeds never replaces a newer one.
+fn spawn_cache_save(feeds: Vec<feed::CachedFeed>) {
+    static REQUESTED: AtomicU64 = AtomicU64::new(0);
+    static SAVED: tokio::sync::Mutex<u64> = tokio::sync::Mutex::const_new(0);
+    let request = REQUESTED.fetch_add(1, Ordering::Relaxed) + 1;
+    tokio::spawn(async move {
+        let mut saved = SAVED.lock().await;
+        if *saved > request {
+            return;
+        }
+        match feed::save_cached_feeds(feeds).await {
+            Ok(()) => *saved = request,
+            Err(e) => log::error!("Failed to save feed cache: {}", e),
+        }
+    });
+}
+
+/// Create a file for editing a note which only this user can read. It is
+/// created anew, so that a file or link left in the shared temporary
+/// directory under the same name is never written through.
+fn create_note_file() -> Result<(PathBuf, File), std::io::Error> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut attempt = 0;
+    loop {
+        let path = std::env::temp_dir().join(format!(
+            "remy-note-{}-{}.md",
+            std::process::id(),
+            attempt
+        ));
+        match options.open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e)
+                if e.kind() == std::io::ErrorKind::AlreadyExists
+                    && attempt < 100 =>
+            {
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
     }
-    Ok(())
+}
+
+/// Edit `text` in `$VISUAL` or `$EDITOR` as a Markdown file, returning the
+/// edited text.
+fn run_editor(text: &str) -> Result<String, std::io::Error> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+    let (path, mut file) = create_note_file()?;
+    file.write_all(text.as_bytes())?;
+    drop(file);
+    let status = std::process::Command::new(program)
+        .args(words)
+        .arg(&path)
+        .status();
+    let result = match status {
+        Ok(status) if status.success() => std::fs::read_to_string(&path),
+        Ok(status) => Err(std::io::Error::other(format!(
+            "{} exited with {}",
+            program, status
+        ))),
+        Err(e) => Err(e),
+    };
+    let _ = std::fs::remove_file(&path);
+    result
+}
+
+/// Suspend the interface while the note of the selected entry is edited.
+fn edit_note(
+    state: &mut state::State,
+) -> Result<DefaultTerminal, std::io::Error> {
+    crossterm::execute!(
+        std::io::stdout(),
+        crossterm::event::DisableMouseCapture
+    )?;
+    ratatui::restore();
+    let result = run_editor(state.selected_note().unwrap_or(""));
+    init_crossterm();
+    let terminal = ratatui::init();
+    match result {
+        Ok(note) => state.set_selected_note(note),
+        Err(e) => {
+            log::error!("Failed to edit note: {}", e);
+            state.status = Some(format!("Failed to edit note: {}", e));
+        }
+    }
+    Ok(terminal)
 }
 
 fn run(
     mut terminal: DefaultTerminal,
     state: &mut state::State,
-    mut feed_rx: mpsc::Receiver<(Vec<feed_rs::model::Feed>, Vec<String>)>,
+    feed_tx: mpsc::Sender<feed::FeedUpdate>,
+    mut feed_rx: mpsc::Receiver<feed::FeedUpdate>,
 ) -> Result<(), std::io::Error> {
     loop {
+        state.tick();
+        if state.note_requested {
+            state.note_requested = false;
+            terminal = edit_note(state)?;
+            state.images.redraw();
+        }
+        // The index is updated once the cached entries are known, as it would
+        // otherwise drop every entry which is not yet loaded
+        if state.index_dirty && !state.loading {
+            state.index_dirty = false;
+            spawn_index_update(state);
+        }
         terminal.draw(|f| render(f, state))?;
-        if let Ok((feeds, urls)) = feed_rx.try_recv() {
-            state.update_feeds(feeds.clone());
-            tokio::spawn(async move {
-                let _ = feed::save_cached_feeds(&feeds, &urls).await;
-            });
+        state.images.flush(terminal.backend_mut())?;
+        if let Ok(update) = feed_rx.try_recv() {
+            handle_feed_update(update, state, &feed_tx);
+        }
+        if state.refresh_requested {
+            state.refresh_requested = false;
+            spawn_refresh(state, feed_tx.clone());
         }
         if event::poll(std::time::Duration::from_millis(100))? {
-            match event::read()? {
-                Event::Key(event) if handle_key_event(event, state) => {
-                    break Ok(());
+            let action = match event::read()? {
+                Event::Key(event) => {
+                    state.status = None;
+                    key_action(event, state)
                 }
                 Event::Mouse(event) => {
-                    handle_mouse_event(event, &terminal, state)?;
+                    mouse_action(event, terminal.size()?.width)
+                }
+                Event::Resize(..) => {
+                    state.images.redraw();
+                    None
                 }
-                _ => {}
+                _ => None,
+            };
+            if let Some(action) = action {
+                action::dispatch(state, action);
             }
         }
+        if state.quit_requested {
+            break Ok(());
+        }
     }
 }
 
 const FEED_PATH: &str = "feeds.txt";
 
+/// Load the cache and search index while the interface starts, then fetch
+/// the feeds. Fetching starts at once, but the fetched feeds are only sent
+/// once the cached feeds have been, so that they are merged into them.
+fn spawn_startup(
+    state: &state::State,
+    feed_tx: mpsc::Sender<feed::FeedUpdate>,
+) {
+    let search_index = state.search_index.clone();
+    let subscriptions = state.subscriptions.clone();
+    let read_only = state.read_only;
+    tokio::spawn(async move {
+        let fetch = tokio::spawn(feed::get(subscriptions));
+        let cached = feed::load_cached_feeds(read_only).await;
+        let index =
+            tokio::task::spawn_blocking(move || search::load(read_only)).await;
+        match index.map_err(std::io::Error::other).and_then(|r| r) {
+            Ok(index) => {
+                if let Ok(mut search_index) = search_index.lock() {
+                    *search_index = index;
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                log::error!("Failed to load search index: {}", e);
+                if e.kind() == std::io::ErrorKind::Unsupported
+                    && let Ok(mut search_index) = search_index.lock()
+                {
+                    search_index.read_only = true;
+                }
+            }
+        }
+        let _ = feed_tx.send(feed::FeedUpdate::Cached(cached)).await;
+        if let Ok(Ok(feeds)) = fetch.await {
+            let _ = feed_tx.send(feed::FeedUpdate::Fetched(feeds)).await;
+        }
+    });
+}
+
+/// Fetch the feeds again, to be merged into the current ones.
+fn spawn_refresh(
+    state: &mut state::State,
+    feed_tx: mpsc::Sender<feed::FeedUpdate>,
+) {
+    state.status = Some("Refreshing feeds…".to_string());
+    let subscriptions = state.subscriptions.clone();
+    tokio::spawn(async move {
+        match feed::get(subscriptions).await {
+            Ok(feeds) => {
+                let _ = feed_tx.send(feed::FeedUpdate::Fetched(feeds)).await;
+            }
+            Err(e) => log::error!("Failed to refresh feeds: {}", e),
+        }
+    });
+}
+
+/// A missing file is expected on first run, any other failure to load is
+/// logged and reported in the UI.
+fn or_warn<T: Default>(
+    result: Result<T, std::io::Error>,
+    what: &str,
+    warnings: &mut Vec<String>,
+) -> T {
+    result.unwrap_or_else(|e| {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            log::error!("Failed to load {}: {}", what, e);
+            warnings.push(format!("Failed to load {}: {}", what, e));
+        }
+        T::default()
+    })
+}
+
 #[tokio::main]
 async fn main() -> Result<(), std::io::Error> {
+    let cli = cli::Cli::parse();
     init_logger();
+    if let Some(command) = cli.command {
+        return cli::run(command).await;
+    }
+    // Held until remy exits, and taken before the terminal is set up so
+    // that an error leaves it as it was
+    let lock = lock::acquire()?;
+    let mut read_only = lock.is_none();
     init_crossterm();
     let (feed_tx, feed_rx) = mpsc::channel(1);
-    let initial_feeds = feed::load_cached_feeds().await.unwrap_or_default();
-    let mut state = state::State::new(initial_feeds);
-    let feed_tx_clone = feed_tx.clone();
-    tokio::spawn(async move {
-        if let Ok((feeds, urls)) = feed::get(FEED_PATH).await {
-            let _ = feed_tx_clone.send((feeds, urls)).await;
-        }
-    });
+    let mut warnings = vec![];
+    if read_only {
+        warnings.push("remy is already running, opened read-only".to_string());
+    }
+    let entry_states = entry_state::load(read_only);
+    // Saving would overwrite the state written by the newer version
+    if let Err(e) = &entry_states
+        && e.kind() == std::io::ErrorKind::Unsupported
+    {
+        read_only = true;
+    }
+    let entry_states = or_warn(entry_states, "entry state", &mut warnings);
+    let config = or_warn(config::load(), "config", &mut warnings);
+    let subscriptions =
+        or_warn(feed::get_subscriptions(FEED_PATH), FEED_PATH, &mut warnings);
+    let keymap = or_warn(keymap::load(), "keymap", &mut warnings);
+    let mut state = state::State::new(vec![], entry_states, config);
+    state.read_only = read_only;
+    state.images.read_only = state.read_only;
+    state.subscriptions = subscriptions;
+    state.keymap = keymap;
+    if !warnings.is_empty() {
+        state.status = Some(warnings.join("; "));
+    }
+    spawn_startup(&state, feed_tx.clone());
     let terminal = ratatui::init();
-    let result = run(terminal, &mut state, feed_rx);
+    let result = run(terminal, &mut state, feed_tx, feed_rx);
     ratatui::restore();
     result
 }
@@ -1,53 +1,206 @@
+use crate::action::Action;
+use crate::config::Grouping;
+use crate::entry_state::Highlight;
+use crate::highlight::{highlight_block, language_from_class};
+use crate::keymap::Keymap;
 use crate::popup::Popup;
-use crate::state::{EntryWithAuthor, SelectedWindow, State};
+use crate::state::{
+    EntryWithAuthor, FeedFilter, SearchHit, SelectedWindow, State, View,
+};
+use crate::table;
+use chrono::{DateTime, Utc};
 use ratatui::prelude::*;
 use ratatui::text::{Line, Span, Text};
-use ratatui::widgets::{Block, Borders, List, ListItem, Paragraph, Wrap};
+use ratatui::widgets::{
+    Block, Borders, Clear, List, ListItem, Paragraph, Scrollbar,
+    ScrollbarOrientation, ScrollbarState, Wrap,
+};
 use scraper::Html;
+use scraper::node::Element;
 use std::rc::Rc;
+use url::Url;
 
-fn entry_to_list_item<'a>(entry: &'a EntryWithAuthor) -> ListItem<'a> {
-    let content = entry
-        .entry
-        .title
-        .as_ref()
-        .map(|t| t.content.as_str())
-        .unwrap_or("No Title");
-    let updated = entry
-        .entry
-        .updated
+fn entry_to_list_item<'a>(
+    entry: &'a EntryWithAuthor,
+    date: Option<DateTime<Utc>>,
+    read: bool,
+    starred: bool,
+) -> ListItem<'a> {
+    let content = entry.title();
+    let date = date
         .map(|d| d.format("%d/%m/%Y").to_string())
         .unwrap_or_else(|| "Unknown Date".to_string());
-    let mut display_text = Text::from(content);
+    let mut title = Line::from(content);
+    if starred {
+        title.spans.insert(0, Span::raw("★ ").yellow());
+    }
+    if !read {
+        title = title.bold();
+    }
+    let mut display_text = Text::from(title);
     display_text.push_line(
-        Line::from(format!("{} - {}", entry.author, updated))
+        Line::from(format!("{} - {}", entry.author, date))
             .italic()
             .right_aligned(),
     );
     ListItem::new(display_text)
 }
 
-fn get_help_line() -> Line<'static> {
+fn sidebar_node_to_list_item(
+    name: String,
+    unread: usize,
+    indent: usize,
+) -> ListItem<'static> {
+    let mut line = Line::from(format!("{}{}", " ".repeat(indent), name));
+    if unread > 0 {
+        line = line.bold();
+        line.push_span(Span::raw(format!(" ({})", unread)).blue());
+    }
+    ListItem::new(line)
+}
+
+fn render_sidebar(frame: &mut Frame, area: Rect, state: &mut State) {
+    let counts = state.unread_counts();
+    let feed_unread = |url: &str| counts.get(url).copied().unwrap_or(0);
+    let items: Vec<ListItem> = state
+        .sidebar_nodes()
+        .into_iter()
+        .map(|node| match node {
+            FeedFilter::All => sidebar_node_to_list_item(
+                "All".to_string(),
+                counts.values().sum(),
+                0,
+            ),
+            FeedFilter::Tag(tag) => {
+                let unread = state
+                    .subscriptions
+                    .iter()
+                    .filter(|s| s.tags.contains(&tag))
+                    .map(|s| feed_unread(&s.url))
+                    .sum();
+                sidebar_node_to_list_item(format!("#{}", tag), unread, 0)
+            }
+            FeedFilter::Feed(url) => sidebar_node_to_list_item(
+                state.feed_title(&url).to_string(),
+                feed_unread(&url),
+                1,
+            ),
+        })
+        .collect();
+    let mut block = Block::new().borders(Borders::ALL).title(" Feeds ");
+    if state.selected_window == SelectedWindow::Sidebar {
+        block = block.border_style(Style::new().blue());
+    }
+    state.sidebar.area = block.inner(area);
+    let list = List::new(items)
+        .block(block)
+        .highlight_style(Style::new().reversed());
+    frame.render_stateful_widget(list, area, &mut state.sidebar.list_state);
+}
+
+fn get_help_line(keymap: &Keymap) -> Line<'static> {
+    let Some(keys) = keymap.sequences(Action::ToggleHelp).into_iter().next()
+    else {
+        return Line::default();
+    };
     Line::from(vec![
         Span::raw(" Press "),
-        Span::styled("h", Style::new().bold()),
+        Span::styled(keys, Style::new().bold()),
         Span::raw(" for controls "),
     ])
 }
 
 fn render_entry_list(frame: &mut Frame, area: Rect, state: &mut State) {
-    let list_items: Vec<ListItem> =
-        state.entries.iter().map(entry_to_list_item).collect();
-    let mut block = Block::new()
-        .borders(Borders::ALL)
-        .title_bottom(get_help_line().centered());
+    let entry_states = &state.entry_states;
+    let sort = state.sort;
+    let mut headers = state.headers.iter().peekable();
+    let mut list_items: Vec<ListItem> = vec![];
+    for (index, &i) in state.visible.iter().enumerate() {
+        if let Some((_, label)) = headers.next_if(|(start, _)| *start == index)
+        {
+            list_items.push(group_header_to_list_item(label));
+        }
+        let e = &state.entries[i];
+        let (feed_url, id) = (&e.feed_url, &e.entry.id);
+        list_items.push(entry_to_list_item(
+            e,
+            e.display_date(sort),
+            entry_states.is_read(feed_url, id),
+            entry_states.is_starred(feed_url, id),
+        ));
+    }
+    let mut block = Block::new().borders(Borders::ALL);
+    // Statuses such as link URLs can be long, so they take the place of the
+    // help line
+    if state.status.is_none() {
+        block = block.title_bottom(get_help_line(&state.keymap).centered());
+    }
+    if state.view == View::Starred {
+        block = block.title(" Starred ");
+    }
+    match &state.feed_filter {
+        FeedFilter::All => {}
+        FeedFilter::Tag(tag) => block = block.title(format!(" #{} ", tag)),
+        FeedFilter::Feed(url) => {
+            block = block.title(format!(" {} ", state.feed_title(url)));
+        }
+    }
+    if state.loading {
+        block = block.title(" Loading… ");
+    }
+    let mut order = format!(" by {} ", state.sort.name());
+    if state.group_by != Grouping::None {
+        order = format!("{}· grouped by {} ", order, state.group_by.name());
+    }
+    block = block.title(Line::from(order).right_aligned());
+    if state.read_only {
+        block = block.title(Line::from(" read-only ").right_aligned());
+    }
+    if let Some(status) = &state.status {
+        block = block.title_bottom(Line::from(format!(" {} ", status)).red());
+    }
     if state.selected_window == SelectedWindow::EntryList {
         block = block.border_style(Style::new().blue());
     }
-    let list = List::new(list_items)
-        .block(block)
-        .highlight_style(Style::new().reversed());
-    frame.render_stateful_widget(list, area, &mut state.list_state);
+    let mut list_area = block.inner(area);
+    frame.render_widget(block, area);
+    if state.filter.editing || !state.filter.input.is_empty() {
+        let [list, filter] =
+            Layout::vertical([Constraint::Fill(1), Constraint::Length(1)])
+                .areas(list_area);
+        render_filter_line(frame, filter, state);
+        list_area = list;
+    }
+    // Keep the header of the selected entry on screen when it is the first
+    // of its group
+    let row = state.list_row(state.selected_entry);
+    let is_first_in_group = state
+        .headers
+        .iter()
+        .any(|(i, _)| *i == state.selected_entry);
+    if is_first_in_group && state.list_state.offset() == row {
+        *state.list_state.offset_mut() = row - 1;
+    }
+    state.list_area = list_area;
+    let list = List::new(list_items).highlight_style(Style::new().reversed());
+    frame.render_stateful_widget(list, list_area, &mut state.list_state);
+}
+
+fn group_header_to_list_item(label: &str) -> ListItem<'_> {
+    ListItem::new(Line::from(label).bold().blue())
+}
+
+fn render_filter_line(frame: &mut Frame, area: Rect, state: &State) {
+    let mut line = Line::from(vec![
+        Span::raw("/").blue(),
+        Span::raw(state.filter.input.clone()),
+    ]);
+    if state.filter.editing {
+        line.push_span(Span::raw(" ").reversed());
+    }
+    let count = Line::from(format!("{} ", state.visible.len())).dim();
+    frame.render_widget(Paragraph::new(line), area);
+    frame.render_widget(Paragraph::new(count.right_aligned()), area);
 }
 
 #[derive(Debug, Clone)]
@@ -90,7 +243,7 @@ impl StyledText {
     }
 }
 
-fn is_block_element(tag: &str) -> bool {
+pub fn is_block_element(tag: &str) -> bool {
     matches!(
         tag,
         "p" | "div"
@@ -110,14 +263,24 @@ fn is_block_element(tag: &str) -> bool {
             | "header"
             | "footer"
             | "nav"
+            | "table"
+            | "thead"
+            | "tbody"
+            | "tfoot"
+            | "tr"
+            | "caption"
+            | "hr"
+            | "dl"
+            | "dt"
+            | "dd"
     )
 }
 
-fn is_whitespace_preserved(tags: &[String]) -> bool {
+pub fn is_whitespace_preserved(tags: &[String]) -> bool {
     tags.iter().any(|t| t == "pre" || t == "code")
 }
 
-fn normalize_whitespace(text: &str, preserve: bool) -> String {
+pub fn normalize_whitespace(text: &str, preserve: bool) -> String {
     if preserve {
         text.to_string()
     } else {
@@ -158,10 +321,25 @@ fn apply_tag_styles(styled: &mut StyledText, tag: &str) {
             styled.in_pre = true;
             styled.color = Some(Color::Green);
         }
-        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+        "h1" => {
             styled.bold = true;
+            styled.underline = true;
             styled.color = Some(Color::Yellow);
         }
+        "h2" => {
+            styled.bold = true;
+            styled.color = Some(Color::Yellow);
+        }
+        "h3" => {
+            styled.bold = true;
+            styled.color = Some(Color::Cyan);
+        }
+        "h4" | "dt" => styled.bold = true,
+        "h5" => {
+            styled.bold = true;
+            styled.italic = true;
+        }
+        "h6" => styled.italic = true,
         "a" => {
             styled.color = Some(Color::Blue);
             styled.underline = true;
@@ -182,9 +360,117 @@ fn flush_current_line(
     }
 }
 
-fn add_list_prefix(current_line: &mut Vec<StyledText>, list_depth: usize) {
+fn add_list_prefix(
+    current_line: &mut Vec<StyledText>,
+    list_depth: usize,
+    marker: &str,
+) {
     let indent = "  ".repeat(list_depth);
-    current_line.push(StyledText::new(format!("{}• ", indent)));
+    current_line.push(StyledText::new(format!("{}{}", indent, marker)));
+}
+
+/// An `<ol>` or `<ul>` being visited.
+struct HtmlList {
+    ordered: bool,
+    /// The number of the next item of an ordered list.
+    next: usize,
+    /// The `type` of an ordered list: `1`, `a`, `A`, `i` or `I`.
+    kind: char,
+}
+
+impl HtmlList {
+    fn new(element: &Element) -> Self {
+        let kind = match element.attr("type") {
+            Some(kind @ ("a" | "A" | "i" | "I")) => kind.chars().next(),
+            _ => None,
+        };
+        Self {
+            ordered: element.name() == "ol",
+            next: element
+                .attr("start")
+                .and_then(|start| start.trim().parse().ok())
+                .unwrap_or(1),
+            kind: kind.unwrap_or('1'),
+        }
+    }
+
+    /// The marker of the next item, which may set its own number with
+    /// `value`, nested `depth` lists deep.
+    fn marker(&mut self, item: &Element, depth: usize) -> String {
+        if !self.ordered {
+            return format!("{} ", ["•", "◦", "▪"][depth % 3]);
+        }
+        let number = item
+            .attr("value")
+            .and_then(|value| value.trim().parse().ok())
+            .unwrap_or(self.next);
+        self.next = number.saturating_add(1);
+        let numeral = match self.kind {
+            'a' => alphabetic(number),
+            'A' => alphabetic(number).to_uppercase(),
+            'i' => roman(number),
+            'I' => roman(number).to_uppercase(),
+            _ => number.to_string(),
+        };
+        format!("{}. ", numeral)
+    }
+}
+
+/// Numbers from 1 as a, b, ..., z, aa, ab, ...
+fn alphabetic(mut number: usize) -> String {
+    let mut letters = vec![];
+    while number > 0 {
+        number -= 1;
+        letters.push((b'a' + (number % 26) as u8) as char);
+        number /= 26;
+    }
+    letters.iter().rev().collect()
+}
+
+fn roman(mut number: usize) -> String {
+    if number == 0 || number >= 4000 {
+        return number.to_string();
+    }
+    let numerals = [
+        (1000, "m"),
+        (900, "cm"),
+        (500, "d"),
+        (400, "cd"),
+        (100, "c"),
+        (90, "xc"),
+        (50, "l"),
+        (40, "xl"),
+        (10, "x"),
+        (9, "ix"),
+        (5, "v"),
+        (4, "iv"),
+        (1, "i"),
+    ];
+    let mut result = String::new();
+    for (value, numeral) in numerals {
+        while number >= value {
+            result.push_str(numeral);
+            number -= value;
+        }
+    }
+    result
+}
+
+/// What a list item, quote or description adds to the start of each of its
+/// lines.
+struct Indent {
+    /// Taken by the first line, such as the marker of a list item.
+    first: Option<Span<'static>>,
+    rest: Span<'static>,
+}
+
+impl Indent {
+    fn new(text: &str) -> Self {
+        Self {
+            first: None,
+            rest: Span::raw(text.to_string()),
+        }
+    }
 }
 
 fn should_add_spacing_after(tag_name: &str) -> bool {
@@ -247,178 +533,1069 @@ fn handle_block_closing(
     }
 }
 
-fn traverse_element(
+/// Callbacks for the depth-first walk over an HTML document made by
+/// `traverse_element`. `tag_stack` holds the names of the enclosing elements.
+pub trait HtmlVisitor {
+    fn open_element(&mut self, element: &Element, tag_stack: &[String]);
+    fn close_element(&mut self, element: &Element, tag_stack: &[String]);
+    fn text(&mut self, text: &str, tag_stack: &[String]);
+}
+
+pub fn traverse_element(
     element_ref: scraper::ElementRef,
     tag_stack: &mut Vec<String>,
-    current_line: &mut Vec<StyledText>,
-    lines: &mut Vec<Line<'static>>,
-    list_depth: &mut usize,
+    visitor: &mut impl HtmlVisitor,
 ) {
-    let tag_name = element_ref.value().name().to_string();
-    let is_block = is_block_element(&tag_name);
-    handle_block_opening(is_block, current_line, lines);
-    if tag_name == "ul" || tag_name == "ol" {
-        *list_depth += 1;
-    }
-    if tag_name == "li" {
-        add_list_prefix(current_line, *list_depth);
-    }
-    tag_stack.push(tag_name.clone());
+    let element = element_ref.value();
+    visitor.open_element(element, tag_stack);
+    tag_stack.push(element.name().to_string());
     for child in element_ref.children() {
         match child.value() {
             scraper::node::Node::Element(_) => {
                 if let Some(child_ref) = scraper::ElementRef::wrap(child) {
-                    traverse_element(
-                        child_ref,
-                        tag_stack,
-                        current_line,
-                        lines,
-                        list_depth,
-                    );
+                    traverse_element(child_ref, tag_stack, visitor);
                 }
             }
             scraper::node::Node::Text(text) => {
-                process_text_node(&text.text, tag_stack, current_line, lines);
+                visitor.text(&text.text, tag_stack);
             }
             _ => {}
         }
     }
     tag_stack.pop();
-    if tag_name == "ul" || tag_name == "ol" {
-        *list_depth = list_depth.saturating_sub(1);
+    visitor.close_element(element, tag_stack);
+}
+
+/// A link of an entry's content, numbered by its position in `links`.
+#[derive(Clone)]
+pub struct Link {
+    pub url: String,
+    /// The content line of its first reference.
+    pub line: usize,
+}
+
+/// An image of an entry's content, drawn over blank lines.
+pub struct InlineImage {
+    pub url: String,
+    /// The first of the lines it is drawn over.
+    pub line: usize,
+    /// The columns and rows it takes up.
+    pub size: (u16, u16),
+}
+
+/// The content of an entry as shown in the content pane, with the links it
+/// contains listed as footnotes at the end.
+pub struct Document {
+    pub lines: Vec<Line<'static>>,
+    pub links: Vec<Link>,
+    pub images: Vec<InlineImage>,
+}
+
+/// The size an image is shown at, or None if it is not shown.
+pub type ImageSize<'a> = &'a dyn Fn(&str) -> Option<(u16, u16)>;
+
+/// The reference shown after the text of link `index`, like `[3]`.
+pub fn link_marker(index: usize) -> String {
+    format!("[{}]", index + 1)
+}
+
+/// Builds the styled lines shown in the content pane.
+#[derive(Default)]
+struct LineBuilder<'a> {
+    current_line: Vec<StyledText>,
+    lines: Vec<Line<'static>>,
+    lists: Vec<HtmlList>,
+    indents: Vec<Indent>,
+    /// The lines before this have been wrapped and indented.
+    decorated: usize,
+    /// The entry's link, which relative hrefs are resolved against.
+    base: Option<Url>,
+    links: Vec<Link>,
+    /// The link of each `<a>` being visited, if it has an href.
+    open_links: Vec<Option<usize>>,
+    images: Vec<InlineImage>,
+    image_size: Option<ImageSize<'a>>,
+    /// Width of the content pane, which tables are laid out in.
+    width: usize,
+    /// The outermost table being visited. Tables nested in its cells are
+    /// flattened into them.
+    table: Option<TableBuilder>,
+    table_depth: usize,
+    /// The `<pre>` being visited, which is highlighted once all of its text
+    /// has been seen.
+    code_block: Option<CodeBlock>,
+}
+
+struct CodeBlock {
+    language: Option<String>,
+    text: String,
+}
+
+/// A cell of a table being visited, and the line of it being built.
+#[derive(Default)]
+struct TableCell {
+    current_line: Vec<StyledText>,
+    cell: table::Cell,
+}
+
+#[derive(Default)]
+struct TableBuilder {
+    rows: Vec<Vec<TableCell>>,
+    caption: TableCell,
+    in_head: bool,
+    in_caption: bool,
+}
+
+impl TableBuilder {
+    /// The cell or caption text is added to, if any.
+    fn current_cell(&mut self) -> Option<&mut TableCell> {
+        if self.in_caption {
+            return Some(&mut self.caption);
+        }
+        self.rows.last_mut().and_then(|row| row.last_mut())
+    }
+}
+
+impl LineBuilder<'_> {
+    fn indent_width(&self) -> usize {
+        self.indents.iter().map(|indent| indent.rest.width()).sum()
+    }
+
+    /// Indent the lines added since this was last called, wrapping them
+    /// first so that the indentation carries on across wrapped lines.
+    fn decorate(&mut self) {
+        let added = self.lines.len();
+        let new_lines = self.lines.split_off(self.decorated);
+        let available = self.width.saturating_sub(self.indent_width());
+        let mut starts = vec![];
+        for line in new_lines {
+            starts.push(self.lines.len());
+            if self.indents.is_empty() || line.width() == 0 {
+                self.lines.push(line);
+                continue;
+            }
+            let pieces = if available > 0 && line.width() > available {
+                table::wrap_line(&line, available)
+            } else {
+                vec![line.clone()]
+            };
+            for piece in pieces {
+                let piece = self.indented(piece.style(line.style));
+                self.lines.push(piece);
+            }
+        }
+        // Links refer to the line holding their reference
+        let end = self.lines.len();
+        for (index, link) in self.links.iter_mut().enumerate() {
+            if link.line < self.decorated {
+                continue;
+            }
+            if link.line >= added {
+                link.line = link.line.min(end);
+                continue;
+            }
+            let start = starts[link.line - self.decorated];
+            let next = starts
+                .get(link.line - self.decorated + 1)
+                .copied()
+                .unwrap_or(end);
+            let marker = link_marker(index);
+            link.line = (start..next)
+                .find(|&i| span_offset(&self.lines[i], &marker).is_some())
+                .unwrap_or(start);
+        }
+        self.decorated = end;
+    }
+
+    /// A line with the indentation of the lists and quotes it is in.
+    fn indented(&mut self, line: Line<'static>) -> Line<'static> {
+        let mut spans: Vec<Span<'static>> = self
+            .indents
+            .iter_mut()
+            .map(|indent| indent.first.take().unwrap_or(indent.rest.clone()))
+            .collect();
+        spans.extend(line.spans);
+        Line::from(spans).style(line.style)
+    }
+
+    fn open_indent(&mut self, element: &Element) {
+        if self.table.is_some() {
+            return;
+        }
+        match element.name() {
+            "ul" | "ol" if self.lists.is_empty() => {
+                self.indents.push(Indent::new("  "))
+            }
+            "li" => {
+                let depth = self.lists.len().saturating_sub(1);
+                let marker = match self.lists.last_mut() {
+                    Some(list) => list.marker(element, depth),
+                    None => "• ".to_string(),
+                };
+                self.indents.push(Indent {
+                    rest: Span::raw(" ".repeat(marker.chars().count())),
+                    first: Some(Span::raw(marker)),
+                });
+            }
+            "blockquote" => {
+                let bar = Span::raw("│ ").dim();
+                self.indents.push(Indent {
+                    first: None,
+                    rest: bar,
+                });
+            }
+            "dd" => self.indents.push(Indent::new("    ")),
+            "hr" => {
+                let width = self.width.saturating_sub(self.indent_width());
+                self.lines.push(Line::from("─".repeat(width)).dim());
+            }
+            _ => {}
+        }
+    }
+
+    fn close_indent(&mut self, tag_name: &str) {
+        if self.table.is_some() {
+            return;
+        }
+        let indented = match tag_name {
+            "ul" | "ol" => self.lists.len() == 1,
+            "li" | "blockquote" | "dd" => true,
+            _ => false,
+        };
+        if indented {
+            self.indents.pop();
+        }
+    }
+
+    /// The line being built and the lines built so far, of the current
+    /// table cell if in a table.
+    fn target(&mut self) -> (&mut Vec<StyledText>, &mut Vec<Line<'static>>) {
+        if let Some(cell) = self.table.as_mut().and_then(|t| t.current_cell()) {
+            return (&mut cell.current_line, &mut cell.cell.lines);
+        }
+        (&mut self.current_line, &mut self.lines)
     }
-    handle_block_closing(&tag_name, is_block, current_line, lines);
-    if tag_name == "br" {
+
+    fn flush(&mut self) {
+        let (current_line, lines) = self.target();
         flush_current_line(current_line, lines);
     }
+
+    fn open_table_element(&mut self, element: &Element) {
+        let tag_name = element.name();
+        if tag_name == "table" {
+            self.table_depth += 1;
+            if self.table_depth == 1 {
+                self.table = Some(TableBuilder::default());
+            }
+            return;
+        }
+        let Some(table) = self.table.as_mut() else {
+            return;
+        };
+        if self.table_depth > 1 {
+            // Cells of nested tables are kept apart by a space
+            if matches!(tag_name, "td" | "th") {
+                self.target().0.push(StyledText::new(" ".to_string()));
+            }
+            return;
+        }
+        match tag_name {
+            "thead" => table.in_head = true,
+            "caption" => table.in_caption = true,
+            "tr" => table.rows.push(vec![]),
+            "td" | "th" => {
+                let align = match element.attr("align") {
+                    Some("center") => Alignment::Center,
+                    Some("right") => Alignment::Right,
+                    _ => Alignment::Left,
+                };
+                let cell = TableCell {
+                    current_line: vec![],
+                    cell: table::Cell {
+                        lines: vec![],
+                        header: tag_name == "th" || table.in_head,
+                        align,
+                    },
+                };
+                if table.rows.is_empty() {
+                    table.rows.push(vec![]);
+                }
+                if let Some(row) = table.rows.last_mut() {
+                    row.push(cell);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn close_table_element(&mut self, tag_name: &str) {
+        if self.table_depth == 1
+            && let Some(table) = self.table.as_mut()
+        {
+            match tag_name {
+                "thead" => table.in_head = false,
+                "caption" => {
+                    self.flush();
+                    if let Some(table) = self.table.as_mut() {
+                        table.in_caption = false;
+                    }
+                }
+                "td" | "th" => self.flush(),
+                "table" => self.add_table(),
+                _ => {}
+            }
+        }
+        if tag_name == "table" {
+            self.table_depth = self.table_depth.saturating_sub(1);
+        }
+    }
+
+    /// Lay out the table which was visited below its caption.
+    fn add_table(&mut self) {
+        let Some(mut table) = self.table.take() else {
+            return;
+        };
+        flush_current_line(
+            &mut table.caption.current_line,
+            &mut table.caption.cell.lines,
+        );
+        for line in table.caption.cell.lines {
+            self.lines.push(line.italic());
+        }
+        let rows = table
+            .rows
+            .into_iter()
+            .map(|row| {
+                row.into_iter()
+                    .map(|mut cell| {
+                        flush_current_line(
+                            &mut cell.current_line,
+                            &mut cell.cell.lines,
+                        );
+                        cell.cell
+                    })
+                    .collect()
+            })
+            .collect();
+        let width = self.width.saturating_sub(self.indent_width());
+        self.lines.extend(table::render_table(rows, width));
+        self.lines.push(Line::from(""));
+    }
+
+    fn open_code_block(&mut self, element: &Element) {
+        let language = element.attr("class").and_then(language_from_class);
+        match (element.name(), self.code_block.as_mut()) {
+            ("pre", None) => {
+                self.code_block = Some(CodeBlock {
+                    language,
+                    text: String::new(),
+                })
+            }
+            ("code", Some(block)) if block.language.is_none() => {
+                block.language = language
+            }
+            _ => {}
+        }
+    }
+
+    /// Add the lines of the `<pre>` which was visited, highlighted and on a
+    /// background of their own.
+    fn add_code_block(&mut self) {
+        let Some(block) = self.code_block.take() else {
+            return;
+        };
+        // Blocks in tables are as wide as their longest line
+        let width = match self.table {
+            Some(_) => 0,
+            None => self.width.saturating_sub(self.indent_width()),
+        };
+        let lines =
+            highlight_block(&block.text, block.language.as_deref(), width);
+        self.flush();
+        if self.table.is_some() {
+            self.target().1.extend(lines);
+            return;
+        }
+        // Code is indented but not wrapped with the text around it, which
+        // would trim the padding of its background
+        self.decorate();
+        for line in lines {
+            let line = self.indented(line);
+            self.lines.push(line);
+        }
+        self.decorated = self.lines.len();
+    }
+
+    /// The number of the link to `href`, which is added if it is new.
+    fn link(&mut self, href: &str) -> usize {
+        let url = self
+            .base
+            .as_ref()
+            .and_then(|base| base.join(href).ok())
+            .map_or_else(|| href.to_string(), |url| url.to_string());
+        match self.links.iter().position(|link| link.url == url) {
+            Some(index) => index,
+            None => {
+                // The line is set when the reference is added, once the text
+                // of the link has been seen
+                self.links.push(Link {
+                    url,
+                    line: usize::MAX,
+                });
+                self.links.len() - 1
+            }
+        }
+    }
+
+    /// Add the reference to a link after its text.
+    fn add_link_marker(&mut self, index: usize) {
+        let mut marker = StyledText::new(link_marker(index));
+        marker.color = Some(Color::Cyan);
+        self.target().0.push(marker);
+        let link = &mut self.links[index];
+        // The current line is the next one to be added, and tables are added
+        // once they have been visited
+        link.line = link.line.min(self.lines.len());
+    }
+
+    /// Add an image on lines of its own, or a placeholder with its alt text
+    /// if it cannot be shown. Either way it can be opened as a link.
+    fn add_image(&mut self, element: &Element) {
+        let Some(src) = element.attr("src") else {
+            return;
+        };
+        let index = self.link(src);
+        let url = self.links[index].url.clone();
+        let size = match self.table {
+            Some(_) => None,
+            None => self.image_size.and_then(|size| size(&url)),
+        };
+        let Some(size) = size else {
+            let alt = element.attr("alt").unwrap_or("").trim();
+            let mut placeholder = StyledText::new(match alt {
+                "" => "[image]".to_string(),
+                alt => format!("[image: {}]", alt),
+            });
+            placeholder.italic = true;
+            self.target().0.push(placeholder);
+            self.add_link_marker(index);
+            return;
+        };
+        flush_current_line(&mut self.current_line, &mut self.lines);
+        self.decorate();
+        let link = &mut self.links[index];
+        link.line = link.line.min(self.lines.len());
+        self.images.push(InlineImage {
+            url,
+            line: self.lines.len(),
+            size,
+        });
+        // Blank lines are dropped, so these hold a space
+        for _ in 0..size.1 {
+            self.lines.push(Line::from(" "));
+        }
+        self.decorated = self.lines.len();
+    }
+
+    fn add_footnotes(&mut self) {
+        if self.links.is_empty() {
+            return;
+        }
+        flush_current_line(&mut self.current_line, &mut self.lines);
+        self.lines.push(Line::from("Links").bold().yellow());
+        for (index, link) in self.links.iter().enumerate() {
+            self.lines.push(Line::from(vec![
+                Span::raw(format!("{} ", link_marker(index))).cyan(),
+                Span::raw(link.url.clone()).blue().underlined(),
+            ]));
+        }
+    }
+}
+
+impl HtmlVisitor for LineBuilder<'_> {
+    fn open_element(&mut self, element: &Element, _tag_stack: &[String]) {
+        let tag_name = element.name();
+        let is_block = is_block_element(tag_name);
+        let (current_line, lines) = self.target();
+        handle_block_opening(is_block, current_line, lines);
+        self.decorate();
+        self.open_table_element(element);
+        self.open_indent(element);
+        self.open_code_block(element);
+        if tag_name == "ul" || tag_name == "ol" {
+            self.lists.push(HtmlList::new(element));
+        }
+        // Lists in tables are indented within their cells
+        let depth = self.lists.len();
+        if tag_name == "li"
+            && self.table.is_some()
+            && let Some(list) = self.lists.last_mut()
+        {
+            let marker = list.marker(element, depth - 1);
+            add_list_prefix(self.target().0, depth, &marker);
+        }
+        if tag_name == "a" {
+            let link = element.attr("href").map(|href| self.link(href));
+            self.open_links.push(link);
+        }
+        if tag_name == "img" {
+            self.add_image(element);
+        }
+    }
+
+    fn close_element(&mut self, element: &Element, _tag_stack: &[String]) {
+        let tag_name = element.name();
+        if tag_name == "a"
+            && let Some(Some(index)) = self.open_links.pop()
+        {
+            self.add_link_marker(index);
+        }
+        self.close_table_element(tag_name);
+        if tag_name == "pre" {
+            self.add_code_block();
+        }
+        let is_block = is_block_element(tag_name);
+        let (current_line, lines) = self.target();
+        handle_block_closing(tag_name, is_block, current_line, lines);
+        if tag_name == "br" {
+            self.flush();
+        }
+        self.decorate();
+        self.close_indent(tag_name);
+        if tag_name == "ul" || tag_name == "ol" {
+            self.lists.pop();
+        }
+    }
+
+    fn text(&mut self, text: &str, tag_stack: &[String]) {
+        if let Some(block) = self.code_block.as_mut() {
+            block.text.push_str(text);
+            return;
+        }
+        // Whitespace between the rows and cells of tables
+        if let Some(table) = self.table.as_mut()
+            && table.current_cell().is_none()
+        {
+            return;
+        }
+        let (current_line, lines) = self.target();
+        process_text_node(text, tag_stack, current_line, lines);
+        self.decorate();
+    }
 }
 
-fn document_to_text(document: &str) -> Vec<Line<'static>> {
+/// Render the HTML content of an entry, resolving relative links against
+/// `base`, the entry's own link. Tables are laid out to fit in `width`
+/// columns, and images are given the room `image_size` asks for.
+pub fn render_document(
+    document: &str,
+    base: Option<&str>,
+    width: u16,
+    image_size: ImageSize,
+) -> Document {
     let html = Html::parse_document(document);
-    let mut lines = Vec::new();
-    let mut current_line = Vec::<StyledText>::new();
-    let mut list_depth = 0;
-    let mut tag_stack = Vec::new();
-    traverse_element(
-        html.root_element(),
-        &mut tag_stack,
-        &mut current_line,
-        &mut lines,
-        &mut list_depth,
-    );
-    lines
+    let mut builder = LineBuilder {
+        base: base.and_then(|base| Url::parse(base).ok()),
+        image_size: Some(image_size),
+        width: width as usize,
+        ..LineBuilder::default()
+    };
+    traverse_element(html.root_element(), &mut Vec::new(), &mut builder);
+    flush_current_line(&mut builder.current_line, &mut builder.lines);
+    builder.decorate();
+    builder.add_footnotes();
+    // Empty lines are dropped, so links refer to the lines which are kept
+    let mut lines = vec![];
+    let mut kept_before = vec![];
+    for line in builder.lines {
+        kept_before.push(lines.len());
+        if !line.spans.is_empty()
+            && line.spans.iter().any(|span| !span.content.is_empty())
+        {
+            lines.push(line);
+        }
+    }
+    let mut links = builder.links;
+    for link in &mut links {
+        link.line = kept_before.get(link.line).copied().unwrap_or(0);
+    }
+    let mut images = builder.images;
+    for image in &mut images {
+        image.line = kept_before.get(image.line).copied().unwrap_or(0);
+    }
+    Document {
+        lines,
+        links,
+        images,
+    }
+}
+
+/// Whether each content line is part of one of the highlighted passages.
+/// Blank lines are left as they are.
+fn highlighted_lines(lines: &[Line], highlights: &[Highlight]) -> Vec<bool> {
+    let texts: Vec<String> = lines
+        .iter()
+        .map(|line| line.spans.iter().map(|s| s.content.as_ref()).collect())
+        .collect();
+    let mut highlighted = vec![false; texts.len()];
+    for (start, end) in highlights.iter().filter_map(|h| h.range(&texts)) {
+        for i in start..=end {
+            highlighted[i] = !texts[i].trim().is_empty();
+        }
+    }
+    highlighted
+}
+
+fn render_annotations(
+    frame: &mut Frame,
+    area: Rect,
+    note: Option<&str>,
+    highlight_count: usize,
+) {
+    let mut title = vec![];
+    if note.is_some() {
+        title.push("Note".to_string());
+    }
+    if highlight_count > 0 {
+        let plural = if highlight_count == 1 { "" } else { "s" };
+        title.push(format!("{} highlight{}", highlight_count, plural));
+    }
+    let block = Block::new()
+        .borders(Borders::TOP)
+        .title(format!(" {} ", title.join(" · ")))
+        .title_style(Style::new().yellow());
+    let paragraph = Paragraph::new(note.unwrap_or("").to_string())
+        .italic()
+        .block(block)
+        .wrap(Wrap { trim: false });
+    frame.render_widget(paragraph, area);
+}
+
+const MAX_NOTE_HEIGHT: u16 = 8;
+
+/// The byte offset within a line of the first span with the given text.
+fn span_offset(line: &Line, text: &str) -> Option<usize> {
+    let mut offset = 0;
+    for span in &line.spans {
+        if span.content == text {
+            return Some(offset);
+        }
+        offset += span.content.len();
+    }
+    None
+}
+
+/// Patch styles onto byte ranges of a line, splitting its spans as needed.
+fn style_ranges(
+    mut line: Line<'static>,
+    ranges: &[(usize, usize, Style)],
+) -> Line<'static> {
+    if ranges.is_empty() {
+        return line;
+    }
+    let mut spans = vec![];
+    let mut offset = 0;
+    for span in std::mem::take(&mut line.spans) {
+        let text = span.content.as_ref();
+        let mut cuts = vec![0, text.len()];
+        for &(start, end, _) in ranges {
+            for cut in [start, end] {
+                if cut > offset && cut < offset + text.len() {
+                    cuts.push(cut - offset);
+                }
+            }
+        }
+        cuts.sort();
+        cuts.dedup();
+        for cut in cuts.windows(2) {
+            let (start, end) = (offset + cut[0], offset + cut[1]);
+            let style = ranges
+                .iter()
+                .filter(|r| start >= r.0 && end <= r.1)
+                .fold(span.style, |style, r| style.patch(r.2));
+            spans.push(Span::styled(text[cut[0]..cut[1]].to_string(), style));
+        }
+        offset += text.len();
+    }
+    line.spans = spans;
+    line
+}
+
+fn render_selected_entry(frame: &mut Frame, area: Rect, state: &mut State) {
+    let mut block = Block::new().borders(Borders::ALL);
+    if state.selected_window == SelectedWindow::EntryContent {
+        block = block.border_style(Style::new().blue());
+    }
+    if state.highlighting.is_some() {
+        block = block.title(
+            Line::from(" v/Enter to highlight, Esc to cancel ").right_aligned(),
+        );
+    } else if !state.find.input.is_empty() {
+        let count = match state.find.matches.len() {
+            0 => " No matches ".to_string(),
+            n => format!(" {}/{} ", state.find.current + 1, n),
+        };
+        block = block.title(Line::from(count).right_aligned());
+    }
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+    let note = state.selected_note();
+    let highlights = state.selected_highlights();
+    let mut content_area = inner;
+    if state.find.editing {
+        let [content, prompt] =
+            Layout::vertical([Constraint::Fill(1), Constraint::Length(1)])
+                .areas(content_area);
+        let prompt_line = Line::from(vec![
+            Span::raw("/").blue(),
+            Span::raw(state.find.input.clone()),
+            Span::raw(" ").reversed(),
+        ]);
+        frame.render_widget(Paragraph::new(prompt_line), prompt);
+        content_area = content;
+    }
+    if note.is_some() || !highlights.is_empty() {
+        let note_height = note.map_or(0, |n| n.lines().count() as u16);
+        let [content, footer] = Layout::vertical([
+            Constraint::Fill(1),
+            Constraint::Length(note_height.min(MAX_NOTE_HEIGHT) + 1),
+        ])
+        .areas(content_area);
+        render_annotations(frame, footer, note, highlights.len());
+        content_area = content;
+    }
+    let Document {
+        lines,
+        links,
+        images,
+    } = state.selected_document();
+    let selected_link = state
+        .selected_link
+        .and_then(|i| Some((link_marker(i), links.get(i)?.line)));
+    let rows = match images.is_empty() {
+        true => vec![],
+        false => line_rows(&lines, content_area.width),
+    };
+    let highlighted = highlighted_lines(&lines, highlights);
+    let lines: Vec<Line> = lines
         .into_iter()
-        .filter(|line| {
-            !line.spans.is_empty()
-                && line.spans.iter().any(|span| !span.content.is_empty())
+        .enumerate()
+        .map(|(i, line)| {
+            let line =
+                if state.highlighting.as_ref().is_some_and(|h| h.contains(i)) {
+                    line.reversed()
+                } else if highlighted[i] {
+                    line.patch_style(Style::new().black().on_yellow())
+                } else {
+                    line
+                };
+            let mut ranges: Vec<(usize, usize, Style)> = state
+                .find
+                .matches
+                .iter()
+                .enumerate()
+                .filter(|(_, m)| m.line == i)
+                .map(|(j, m)| {
+                    let style = if j == state.find.current {
+                        Style::new().black().on_light_red()
+                    } else {
+                        Style::new().black().on_cyan()
+                    };
+                    (m.start, m.end, style)
+                })
+                .collect();
+            if let Some((marker, line_index)) = &selected_link
+                && *line_index == i
+                && let Some(start) = span_offset(&line, marker)
+            {
+                ranges.push((
+                    start,
+                    start + marker.len(),
+                    Style::new().reversed(),
+                ));
+            }
+            style_ranges(line, &ranges)
         })
-        .collect()
+        .collect();
+    let paragraph = Paragraph::new(lines).wrap(Wrap { trim: false });
+    render_scrolled(frame, area, content_area, paragraph, state);
+    render_images(frame, content_area, &images, &rows, state);
+}
+
+/// The row at which each line starts once wrapped to `width`.
+fn line_rows(lines: &[Line], width: u16) -> Vec<usize> {
+    let mut row = 0;
+    let mut rows = vec![];
+    for line in lines {
+        rows.push(row);
+        row += Paragraph::new(line.clone())
+            .wrap(Wrap { trim: false })
+            .line_count(width.max(1));
+    }
+    rows
 }
 
-fn render_selected_entry(
+/// Draw the part of each image scrolled into view over the blank lines
+/// left for it.
+fn render_images(
     frame: &mut Frame,
     area: Rect,
-    entry_body: &str,
-    selected_window: SelectedWindow,
-    scroll_offset: u16,
+    images: &[InlineImage],
+    rows: &[usize],
+    state: &mut State,
 ) {
+    let offset = state.entry_scroll_offset as usize;
+    let bottom = offset + area.height as usize;
+    let covered = matches!(
+        state.selected_window,
+        SelectedWindow::HelpPopup | SelectedWindow::SearchPopup
+    );
+    for image in images {
+        let Some(&top) = rows.get(image.line) else {
+            continue;
+        };
+        let (columns, height) = image.size;
+        let start = top.max(offset);
+        let end = (top + height as usize).min(bottom);
+        if start >= end {
+            continue;
+        }
+        let visible = Rect {
+            x: area.x,
+            y: area.y + (start - offset) as u16,
+            width: columns.min(area.width),
+            height: (end - start) as u16,
+        };
+        state.images.draw(
+            &image.url,
+            image.size,
+            (start - top) as u16,
+            visible,
+            covered,
+            frame.buffer_mut(),
+        );
+    }
+}
+
+fn render_selected_entry_raw(frame: &mut Frame, area: Rect, state: &mut State) {
     let mut block = Block::new().borders(Borders::ALL);
-    if selected_window == SelectedWindow::EntryContent {
+    if state.selected_window == SelectedWindow::EntryContent {
         block = block.border_style(Style::new().blue());
     }
-    let lines = document_to_text(entry_body);
-    let paragraph = Paragraph::new(lines)
-        .scroll((scroll_offset, 0))
-        .block(block)
+    let content_area = block.inner(area);
+    frame.render_widget(block, area);
+    let paragraph = Paragraph::new(state.get_selected_entry_body().to_string())
         .wrap(Wrap { trim: false });
-    frame.render_widget(paragraph, area);
+    render_scrolled(frame, area, content_area, paragraph, state);
 }
 
-fn render_selected_entry_raw(frame: &mut Frame, area: Rect, entry_body: &str) {
-    let paragraph = Paragraph::new(entry_body.to_string())
-        .block(Block::new().borders(Borders::ALL))
-        .wrap(Wrap { trim: false });
-    frame.render_widget(paragraph, area);
+/// Draw the content of the entry scrolled to the current offset, which is
+/// kept within the wrapped content in case the pane was resized. Content
+/// taller than the pane gets a scrollbar and the position on the right
+/// border of `area`.
+fn render_scrolled(
+    frame: &mut Frame,
+    area: Rect,
+    content_area: Rect,
+    paragraph: Paragraph,
+    state: &mut State,
+) {
+    let total = paragraph.line_count(content_area.width.max(1));
+    let height = content_area.height as usize;
+    let last = total.saturating_sub(height).min(u16::MAX as usize) as u16;
+    state.entry_scroll_offset = state.entry_scroll_offset.min(last);
+    state.content_area = content_area;
+    let offset = state.entry_scroll_offset;
+    frame.render_widget(paragraph.scroll((offset, 0)), content_area);
+    if total <= height {
+        return;
+    }
+    let mut scrollbar_state =
+        ScrollbarState::new(last as usize + 1).position(offset as usize);
+    frame.render_stateful_widget(
+        Scrollbar::new(ScrollbarOrientation::VerticalRight)
+            .begin_symbol(None)
+            .end_symbol(None),
+        area.inner(Margin::new(0, 1)),
+        &mut scrollbar_state,
+    );
+    let bottom = (offset as usize + height).min(total);
+    let position = Line::from(format!(
+        " line {}/{} ({}%) ",
+        offset + 1,
+        total,
+        bottom * 100 / total
+    ))
+    .right_aligned();
+    let border = Rect {
+        x: area.x + 1,
+        y: area.bottom().saturating_sub(1),
+        width: area.width.saturating_sub(2),
+        height: 1,
+    };
+    frame.render_widget(position, border);
+}
+
+fn get_help_text(keymap: &Keymap) -> Vec<Line<'static>> {
+    keymap
+        .help()
+        .into_iter()
+        .map(|(keys, description)| {
+            Line::from(vec![
+                Span::raw("• "),
+                Span::styled(keys, Style::new().bold()),
+                Span::raw(format!(" to {}", description)),
+            ])
+        })
+        .collect()
 }
 
-fn get_help_text() -> Vec<Line<'static>> {
-    vec![
-        Line::from(vec![
-            Span::raw("• "),
-            Span::styled("Up/Down", Style::new().bold()),
-            Span::raw(" to navigate"),
-        ]),
-        Line::from(vec![
-            Span::raw("• "),
-            Span::styled("Left/Right", Style::new().bold()),
-            Span::raw(" to switch panes"),
-        ]),
-        Line::from(vec![
-            Span::raw("• "),
-            Span::styled("Enter", Style::new().bold()),
-            Span::raw(" to open article in browser"),
-        ]),
-        Line::from(vec![
-            Span::raw("• "),
-            Span::styled("r", Style::new().bold()),
-            Span::raw(" to toggle raw/html view"),
-        ]),
-        Line::from(vec![
-            Span::raw("• "),
-            Span::styled("q", Style::new().bold()),
-            Span::raw(" to quit"),
-        ]),
-    ]
-}
-
-const HELP_POPUP_DIMS: (u16, u16) = (37, 7);
-
-fn render_help_popup(frame: &mut Frame) {
+fn render_help_popup(frame: &mut Frame, state: &State) {
+    let text = get_help_text(&state.keymap);
+    // Room for the borders
+    let width = text.iter().map(Line::width).max().unwrap_or(0) as u16 + 2;
+    let height = text.len() as u16 + 2;
+    let area = frame.area();
+    let width = width.min(area.width);
+    let height = height.min(area.height);
     let area = Rect {
-        x: frame.area().width / 2 - HELP_POPUP_DIMS.0 / 2,
-        y: frame.area().height / 2 - HELP_POPUP_DIMS.1 / 2,
-        width: HELP_POPUP_DIMS.0,
-        height: HELP_POPUP_DIMS.1,
+        x: area.width / 2 - width / 2,
+        y: area.height / 2 - height / 2,
+        width,
+        height,
     };
     let popup = Popup::default()
         .title("Controls")
-        .content(get_help_text())
+        .content(text)
         .border_style(Style::new().blue())
         .title_style(Style::new().bold().blue());
     popup.render(area, frame.buffer_mut());
 }
 
+fn search_hit_to_list_item(hit: &SearchHit) -> ListItem<'static> {
+    let mut snippet = Line::default();
+    let mut position = 0;
+    for &(start, end) in &hit.highlights {
+        snippet.push_span(Span::raw(hit.snippet[position..start].to_string()));
+        snippet.push_span(
+            Span::raw(hit.snippet[start..end].to_string())
+                .bold()
+                .yellow(),
+        );
+        position = end;
+    }
+    snippet.push_span(Span::raw(hit.snippet[position..].to_string()));
+    let mut text = Text::from(Line::from(hit.title.clone()).bold());
+    text.push_line(snippet.italic());
+    ListItem::new(text)
+}
+
+fn render_search_popup(frame: &mut Frame, state: &mut State) {
+    let area = frame.area().inner(Margin {
+        horizontal: frame.area().width / 10,
+        vertical: frame.area().height / 10,
+    });
+    Clear.render(area, frame.buffer_mut());
+    let mut block = Block::new()
+        .title("Search")
+        .title_style(Style::new().bold().blue())
+        .borders(Borders::ALL)
+        .border_style(Style::new().blue());
+    if state.search.indexing {
+        block = block.title(Line::from(" Indexing… ").right_aligned());
+    } else {
+        let count = format!(" {} results ", state.search.hits.len());
+        block = block.title(Line::from(count).right_aligned());
+    }
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+    let [input_area, results_area] =
+        Layout::vertical([Constraint::Length(2), Constraint::Fill(1)])
+            .areas(inner);
+    let input = Paragraph::new(Line::from(vec![
+        Span::raw("> ").blue(),
+        Span::raw(state.search.query.clone()),
+        Span::raw(" ").reversed(),
+    ]))
+    .block(Block::new().borders(Borders::BOTTOM));
+    frame.render_widget(input, input_area);
+    let items: Vec<ListItem> = state
+        .search
+        .hits
+        .iter()
+        .map(search_hit_to_list_item)
+        .collect();
+    let list = List::new(items).highlight_style(Style::new().reversed());
+    frame.render_stateful_widget(
+        list,
+        results_area,
+        &mut state.search.list_state,
+    );
+    state.search.area = results_area;
+}
+
 fn get_layout(frame: &mut Frame, state: &State) -> Rc<[Rect]> {
     Layout::default()
         .direction(Direction::Horizontal)
-        .constraints(vec![
-            Constraint::Percentage((state.separator.position * 100.0) as u16),
-            Constraint::Percentage(
-                ((1.0 - state.separator.position) * 100.0) as u16,
-            ),
-        ])
+        .constraints(
+            state
+                .separators
+                .percentages()
+                .into_iter()
+                .map(Constraint::Percentage),
+        )
         .split(frame.area())
 }
 
 pub fn render(frame: &mut Frame, state: &mut State) {
     let layout = get_layout(frame, state);
-    render_entry_list(frame, layout[0], state);
+    let mut panes = layout.iter().copied();
+    state.pane_areas.clear();
+    state.images.start_frame();
+    if state.sidebar.shown
+        && let Some(area) = panes.next()
+    {
+        render_sidebar(frame, area, state);
+        state.pane_areas.push((SelectedWindow::Sidebar, area));
+    }
+    let (Some(list_area), Some(content_area)) = (panes.next(), panes.next())
+    else {
+        return;
+    };
+    state.pane_areas.extend([
+        (SelectedWindow::EntryList, list_area),
+        (SelectedWindow::EntryContent, content_area),
+    ]);
+    render_entry_list(frame, list_area, state);
     if state.render_raw_html {
-        render_selected_entry_raw(
-            frame,
-            layout[1],
-            state.get_selected_entry_body(),
-        );
+        render_selected_entry_raw(frame, content_area, state);
     } else {
-        render_selected_entry(
-            frame,
-            layout[1],
-            state.get_selected_entry_body(),
-            state.selected_window.clone(),
-            state.entry_scroll_offset,
-        );
+        render_selected_entry(frame, content_area, state);
+    }
+    match state.selected_window {
+        SelectedWindow::HelpPopup => render_help_popup(frame, state),
+        SelectedWindow::SearchPopup => render_search_popup(frame, state),
+        _ => {}
     }
-    if state.selected_window == SelectedWindow::HelpPopup {
-        render_help_popup(frame);
+    if let Some(command) = &state.command_line {
+        render_command_line(frame, command);
     }
 }
+
+/// Draw the command being typed over the bottom row of the screen.
+fn render_command_line(frame: &mut Frame, command: &str) {
+    let area = frame.area();
+    let area = Rect {
+        y: area.bottom().saturating_sub(1),
+        height: area.height.min(1),
+        ..area
+    };
+    let line = Line::from(vec![
+        Span::raw(":").blue(),
+        Span::raw(command.to_string()),
+        Span::raw(" ").reversed(),
+    ]);
+    frame.render_widget(Clear, area);
+    frame.render_widget(Paragraph::new(line), area);
+}
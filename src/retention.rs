@@ -0,0 +1,146 @@
+use crate::config::Retention;
+use crate::entry_state::EntryStates;
+use crate::feed::CachedFeed;
+use chrono::{DateTime, Utc};
+use feed_rs::model::Entry;
+use std::collections::{HashMap, HashSet};
+
+/// Entries exempt from pruning, identified by feed URL and entry id.
+pub type Keep = HashSet<(String, String)>;
+
+/// The starred and annotated entries, and the unread ones if the retention
+/// policy keeps them.
+pub fn entries_to_keep(
+    feeds: &[CachedFeed],
+    entry_states: &EntryStates,
+    retention: &Retention,
+) -> Keep {
+    let mut keep = Keep::new();
+    for CachedFeed { url, feed, .. } in feeds {
+        for entry in &feed.entries {
+            let state = entry_states.get(url, &entry.id);
+            let starred = state.is_some_and(|s| s.starred.is_some());
+            let annotated = state
+                .is_some_and(|s| s.note.is_some() || !s.highlights.is_empty());
+            let unread = !state.is_some_and(|s| s.read);
+            if starred || annotated || (retention.keep_unread && unread) {
+                keep.insert((url.clone(), entry.id.clone()));
+            }
+        }
+    }
+    keep
+}
+
+/// Merge freshly fetched feeds into the cached ones, so that entries are kept
+/// after they drop out of the upstream feed. Fetched entries replace cached
+/// entries with the same id, and feeds which failed to fetch are kept as is.
+/// Entries seen for the first time are given the current time as their
+/// first-seen time.
+pub fn merge(
+    cached: &[CachedFeed],
+    fetched: Vec<CachedFeed>,
+) -> Vec<CachedFeed> {
+    let now = Utc::now();
+    let mut cached: HashMap<&str, &CachedFeed> =
+        cached.iter().map(|c| (c.url.as_str(), c)).collect();
+    let mut merged = vec![];
+    for mut fetched_feed in fetched {
+        if let Some(old) = cached.remove(fetched_feed.url.as_str()) {
+            let ids: HashSet<&str> = fetched_feed
+                .feed
+                .entries
+                .iter()
+                .map(|e| e.id.as_str())
+                .collect();
+            let old_entries: Vec<Entry> = old
+                .feed
+                .entries
+                .iter()
+                .filter(|e| !ids.contains(e.id.as_str()))
+                .cloned()
+                .collect();
+            // Old entries may still have their bodies on disk
+            fetched_feed.bodies = old.bodies.excluding(&ids);
+            fetched_feed.feed.entries.extend(old_entries);
+            fetched_feed.first_seen = old.first_seen.clone();
+        }
+        for entry in &fetched_feed.feed.entries {
+            fetched_feed
+                .first_seen
+                .entry(entry.id.clone())
+                .or_insert(now);
+        }
+        merged.push(fetched_feed);
+    }
+    merged.extend(cached.into_values().cloned());
+    merged
+}
+
+/// Drop the entries pruned by `prune` from `feeds`, which may have changed
+/// since they were pruned.
+pub fn remove(feeds: &mut [CachedFeed], removed: &[(String, String)]) {
+    for cached in feeds {
+        let pruned: HashSet<&str> = removed
+            .iter()
+            .filter(|(url, _)| *url == cached.url)
+            .map(|(_, id)| id.as_str())
+            .collect();
+        if pruned.is_empty() {
+            continue;
+        }
+        cached
+            .feed
+            .entries
+            .retain(|entry| !pruned.contains(entry.id.as_str()));
+        cached
+            .first_seen
+            .retain(|id, _| !pruned.contains(id.as_str()));
+    }
+}
+
+/// The time `days` days ago, or `None` if that is too long ago to represent,
+/// in which case nothing is too old.
+pub fn cutoff(days: u64) -> Option<DateTime<Utc>> {
+    i64::try_from(days)
+        .ok()
+        .and_then(chrono::TimeDelta::try_days)
+        .and_then(|age| Utc::now().checked_sub_signed(age))
+}
+
+/// Remove entries which fall outside the retention policy, returning the
+/// feed URL and id of each entry removed.
+pub fn prune(
+    feeds: &mut [CachedFeed],
+    retention: &Retention,
+    keep: &Keep,
+) -> Vec<(String, String)> {
+    let cutoff = retention.max_age_days.and_then(cutoff);
+    let mut removed = vec![];
+    for cached in feeds {
+        let mut entries = std::mem::take(&mut cached.feed.entries);
+        let date = |entry: &Entry| cached.entry_date(entry);
+        entries.sort_by_key(|e| std::cmp::Reverse(date(e)));
+        let mut count = 0;
+        entries.retain(|entry| {
+            if keep.contains(&(cached.url.clone(), entry.id.clone())) {
+                return true;
+            }
+            count += 1;
+            let too_old = cutoff
+                .zip(date(entry))
+                .is_some_and(|(cutoff, date)| date < cutoff);
+            let too_many = retention
+                .max_entries_per_feed
+                .is_some_and(|max| count > max);
+            if too_old || too_many {
+                removed.push((cached.url.clone(), entry.id.clone()));
+            }
+            !too_old && !too_many
+        });
+        let ids: HashSet<&str> =
+            entries.iter().map(|e| e.id.as_str()).collect();
+        cached.first_seen.retain(|id, _| ids.contains(id.as_str()));
+        cached.feed.entries = entries;
+    }
+    removed
+}
@@ -0,0 +1,368 @@
+use crate::render::{
+    HtmlVisitor, is_block_element, is_whitespace_preserved,
+    normalize_whitespace, traverse_element,
+};
+use crate::state::EntryWithAuthor;
+use crate::storage;
+use scraper::Html;
+use scraper::node::Element;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io;
+
+const DATA_PREFIX: &str = "com.benmandrew.remy";
+const INDEX_FILENAME: &str = "search_index.json";
+const INDEX_VERSION: u32 = 1;
+
+const TITLE_WEIGHT: f32 = 3.0;
+const AUTHOR_WEIGHT: f32 = 2.0;
+const CATEGORY_WEIGHT: f32 = 2.0;
+const BODY_WEIGHT: f32 = 1.0;
+const ANNOTATION_WEIGHT: f32 = 2.0;
+
+// BM25 parameters
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+
+/// The feed URL and entry id of an indexed entry.
+pub type DocKey = (String, String);
+
+#[derive(Serialize, Deserialize)]
+struct Doc {
+    feed_url: String,
+    entry_id: String,
+    /// Changes whenever the indexed content of the entry changes.
+    fingerprint: String,
+    /// Weighted number of terms in the entry.
+    length: f32,
+    terms: Vec<String>,
+}
+
+/// Inverted index over the titles, authors, categories, bodies, notes and
+/// highlights of every cached entry.
+#[derive(Serialize, Deserialize, Default)]
+pub struct SearchIndex {
+    docs: HashMap<u32, Doc>,
+    /// Weighted term frequency of each term in each document.
+    postings: HashMap<String, HashMap<u32, f32>>,
+    next_id: u32,
+    #[serde(skip)]
+    by_key: HashMap<DocKey, u32>,
+    /// Set when the index on disk was written by a newer version, which
+    /// saving this one would overwrite.
+    #[serde(skip)]
+    pub read_only: bool,
+}
+
+pub struct SearchResult {
+    pub key: DocKey,
+    pub score: f32,
+}
+
+/// Collects the text of an HTML document, one line per block.
+#[derive(Default)]
+struct PlainText {
+    out: String,
+}
+
+impl HtmlVisitor for PlainText {
+    fn open_element(&mut self, element: &Element, _tag_stack: &[String]) {
+        if is_block_element(element.name()) || element.name() == "br" {
+            self.out.push('\n');
+        }
+    }
+
+    fn close_element(&mut self, element: &Element, _tag_stack: &[String]) {
+        if is_block_element(element.name()) {
+            self.out.push('\n');
+        }
+    }
+
+    fn text(&mut self, text: &str, tag_stack: &[String]) {
+        if tag_stack.iter().any(|t| t == "script" || t == "style") {
+            return;
+        }
+        let preserve = is_whitespace_preserved(tag_stack);
+        self.out.push_str(&normalize_whitespace(text, preserve));
+    }
+}
+
+pub fn html_to_text(document: &str) -> String {
+    let html = Html::parse_fragment(document);
+    let mut text = PlainText::default();
+    traverse_element(html.root_element(), &mut Vec::new(), &mut text);
+    text.out
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Strip plural suffixes so that e.g. "borrow" matches "borrows".
+fn stem(word: &str) -> String {
+    let word = word.strip_suffix("'s").unwrap_or(word);
+    if word.chars().count() < 4 {
+        return word.to_string();
+    }
+    if let Some(stripped) = word.strip_suffix("ies") {
+        format!("{}y", stripped)
+    } else if word.ends_with("sses")
+        || word.ends_with("ches")
+        || word.ends_with("shes")
+        || word.ends_with("xes")
+    {
+        word[..word.len() - 2].to_string()
+    } else if word.ends_with('s')
+        && !word.ends_with("ss")
+        && !word.ends_with("us")
+        && !word.ends_with("is")
+    {
+        word[..word.len() - 1].to_string()
+    } else {
+        word.to_string()
+    }
+}
+
+pub fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric() && c != '\'')
+        .map(|w| w.trim_matches('\'').to_lowercase())
+        .filter(|w| !w.is_empty())
+        .map(|w| stem(&w))
+}
+
+/// Changes whenever the dates, body or annotation of an entry do, so that
+/// edits upstream are indexed even if they leave the dates alone.
+fn fingerprint(
+    entry: &EntryWithAuthor,
+    body: Option<&str>,
+    annotation: &str,
+) -> String {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    annotation.hash(&mut hasher);
+    format!(
+        "{:?}/{:?}/{:x}",
+        entry.entry.updated,
+        entry.entry.published,
+        hasher.finish()
+    )
+}
+
+impl SearchIndex {
+    fn rebuild_keys(&mut self) {
+        self.by_key = self
+            .docs
+            .iter()
+            .map(|(&id, d)| ((d.feed_url.clone(), d.entry_id.clone()), id))
+            .collect();
+    }
+
+    fn remove(&mut self, id: u32) {
+        if let Some(doc) = self.docs.remove(&id) {
+            for term in &doc.terms {
+                if let Some(posting) = self.postings.get_mut(term) {
+                    posting.remove(&id);
+                    if posting.is_empty() {
+                        self.postings.remove(term);
+                    }
+                }
+            }
+            self.by_key.remove(&(doc.feed_url, doc.entry_id));
+        }
+    }
+
+    fn add(
+        &mut self,
+        entry: &EntryWithAuthor,
+        body: Option<&str>,
+        annotation: &str,
+        fingerprint: String,
+    ) {
+        let mut frequencies: HashMap<String, f32> = HashMap::new();
+        let mut add_field = |text: &str, weight: f32| {
+            for term in tokenize(text) {
+                *frequencies.entry(term).or_default() += weight;
+            }
+        };
+        if let Some(title) = &entry.entry.title {
+            add_field(&title.content, TITLE_WEIGHT);
+        }
+        add_field(&entry.author, AUTHOR_WEIGHT);
+        for category in &entry.entry.categories {
+            add_field(
+                category.label.as_deref().unwrap_or(&category.term),
+                CATEGORY_WEIGHT,
+            );
+        }
+        add_field(&html_to_text(body.unwrap_or("")), BODY_WEIGHT);
+        add_field(annotation, ANNOTATION_WEIGHT);
+        let id = self.next_id;
+        self.next_id += 1;
+        for (term, frequency) in &frequencies {
+            self.postings
+                .entry(term.clone())
+                .or_default()
+                .insert(id, *frequency);
+        }
+        let (feed_url, entry_id) = entry.key();
+        self.by_key.insert((feed_url.clone(), entry_id.clone()), id);
+        self.docs.insert(
+            id,
+            Doc {
+                feed_url,
+                entry_id,
+                fingerprint,
+                length: frequencies.values().sum(),
+                terms: frequencies.into_keys().collect(),
+            },
+        );
+    }
+
+    /// Bring the index in line with `entries` and their notes and
+    /// highlights, indexing only new or changed entries and dropping those no
+    /// longer present. Returns whether the index changed.
+    pub fn update<'a>(
+        &mut self,
+        entries: impl IntoIterator<Item = &'a EntryWithAuthor>,
+        annotations: &HashMap<DocKey, String>,
+    ) -> bool {
+        let mut changed = false;
+        let mut seen = HashSet::new();
+        for entry in entries {
+            let key = entry.key();
+            let annotation = annotations.get(&key).map_or("", String::as_str);
+            let existing = self.by_key.get(&key).copied();
+            seen.insert(key);
+            let body = entry.body_unloaded();
+            let fingerprint = fingerprint(entry, body.as_deref(), annotation);
+            if let Some(id) = existing {
+                if self.docs[&id].fingerprint == fingerprint {
+                    continue;
+                }
+                self.remove(id);
+            }
+            self.add(entry, body.as_deref(), annotation, fingerprint);
+            changed = true;
+        }
+        let stale: Vec<u32> = self
+            .by_key
+            .iter()
+            .filter(|(key, _)| !seen.contains(*key))
+            .map(|(_, &id)| id)
+            .collect();
+        changed |= !stale.is_empty();
+        for id in stale {
+            self.remove(id);
+        }
+        changed
+    }
+
+    /// Rank the indexed entries against `query` with BM25.
+    pub fn query(&self, query: &str, limit: usize) -> Vec<SearchResult> {
+        let terms: HashSet<String> = tokenize(query).collect();
+        if terms.is_empty() || self.docs.is_empty() {
+            return vec![];
+        }
+        let doc_count = self.docs.len() as f32;
+        let average_length =
+            self.docs.values().map(|d| d.length).sum::<f32>() / doc_count;
+        let mut scores: HashMap<u32, f32> = HashMap::new();
+        for term in &terms {
+            let Some(posting) = self.postings.get(term) else {
+                continue;
+            };
+            let n = posting.len() as f32;
+            let idf = ((doc_count - n + 0.5) / (n + 0.5) + 1.0).ln();
+            for (id, &frequency) in posting {
+                let length = self.docs[id].length;
+                let norm = K1 * (1.0 - B + B * length / average_length);
+                *scores.entry(*id).or_default() +=
+                    idf * frequency * (K1 + 1.0) / (frequency + norm);
+            }
+        }
+        let mut results: Vec<SearchResult> = scores
+            .into_iter()
+            .map(|(id, score)| {
+                let doc = &self.docs[&id];
+                SearchResult {
+                    key: (doc.feed_url.clone(), doc.entry_id.clone()),
+                    score,
+                }
+            })
+            .collect();
+        results.sort_by(|a, b| b.score.total_cmp(&a.score));
+        results.truncate(limit);
+        results
+    }
+}
+
+/// A short extract of `text` around the first occurrence of a query term,
+/// along with the byte ranges of the matching words within the extract.
+pub fn snippet(
+    text: &str,
+    query: &str,
+    width: usize,
+) -> (String, Vec<(usize, usize)>) {
+    let terms: HashSet<String> = tokenize(query).collect();
+    let text = text.replace('\n', " ");
+    let mut words: Vec<(usize, &str)> = vec![];
+    let mut word_start = None;
+    for (i, c) in text.char_indices() {
+        let in_word = c.is_alphanumeric() || c == '\'';
+        match word_start {
+            None if in_word => word_start = Some(i),
+            Some(start) if !in_word => {
+                words.push((start, &text[start..i]));
+                word_start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(start) = word_start {
+        words.push((start, &text[start..]));
+    }
+    let is_match =
+        |word: &str| tokenize(word).next().is_some_and(|t| terms.contains(&t));
+    let first = words
+        .iter()
+        .find(|(_, w)| is_match(w))
+        .map_or(0, |(start, _)| *start);
+    let mut start = first.saturating_sub(width / 3);
+    while !text.is_char_boundary(start) {
+        start -= 1;
+    }
+    let mut end = (start + width).min(text.len());
+    while !text.is_char_boundary(end) {
+        end += 1;
+    }
+    let highlights = words
+        .iter()
+        .filter(|(s, w)| *s >= start && s + w.len() <= end && is_match(w))
+        .map(|(s, w)| (s - start, s - start + w.len()))
+        .collect();
+    (text[start..end].to_string(), highlights)
+}
+
+fn get_index_path() -> Result<std::path::PathBuf, io::Error> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix(DATA_PREFIX);
+    xdg_dirs.find_data_file(INDEX_FILENAME).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "search index not found")
+    })
+}
+
+pub fn load(read_only: bool) -> Result<SearchIndex, io::Error> {
+    let mut index: SearchIndex =
+        storage::load(&get_index_path()?, INDEX_VERSION, &[], read_only)?;
+    index.rebuild_keys();
+    Ok(index)
+}
+
+pub fn save(index: &SearchIndex) -> Result<(), io::Error> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix(DATA_PREFIX);
+    let index_path = xdg_dirs
+        .place_data_file(INDEX_FILENAME)
+        .map_err(io::Error::other)?;
+    storage::save(&index_path, INDEX_VERSION, index)
+}
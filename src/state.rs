@@ -1,143 +1,1366 @@
-use feed_rs::model::{Entry, Feed};
-use ratatui::widgets::ListState;
+use crate::cache::{Bodies, Body};
+use crate::config::{Config, Grouping, MarkRead, SortOrder};
+use crate::entry_state::{self, EntryStates, Highlight};
+use crate::feed::{self, CachedFeed, Subscription};
+use crate::filter::{FeedInfo, Query};
+use crate::images::Images;
+use crate::keymap::{KeySequence, Keymap};
+use crate::render::{self, Document};
+use crate::search::{self, SearchIndex};
+use chrono::{DateTime, Datelike, Days, Local, NaiveDate, Utc};
+use feed_rs::model::{Content, Entry, Text};
+use ratatui::layout::{Position, Rect};
+use ratatui::widgets::{ListState, Paragraph, Wrap};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
-#[derive(Clone, PartialEq)]
+const SEARCH_LIMIT: usize = 50;
+const SNIPPET_WIDTH: usize = 120;
+/// Rows the content pane scrolls by for each step of the mouse wheel.
+const CONTENT_SCROLL_ROWS: isize = 3;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum SelectedWindow {
     EntryList,
     EntryContent,
+    Sidebar,
     HelpPopup,
+    SearchPopup,
+}
+
+/// A full-text search result, with a snippet of the matching text.
+pub struct SearchHit {
+    pub key: (String, String),
+    pub title: String,
+    pub snippet: String,
+    /// Byte ranges of the matching words within the snippet.
+    pub highlights: Vec<(usize, usize)>,
+}
+
+#[derive(Default)]
+pub struct Search {
+    pub query: String,
+    pub hits: Vec<SearchHit>,
+    pub list_state: ListState,
+    /// Set while the index is being updated in the background, in which case
+    /// the search is run again once it is free.
+    pub indexing: bool,
+    /// Area of the rendered list of hits as of the last frame.
+    pub area: Rect,
+}
+
+/// A match of the find query, as a byte range within a content line.
+pub struct FindMatch {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// The query typed after `/` in the content pane to find text within the
+/// selected entry.
+#[derive(Default)]
+pub struct Find {
+    pub input: String,
+    /// Set while the query is being typed.
+    pub editing: bool,
+    pub matches: Vec<FindMatch>,
+    /// Index into `matches` of the match jumped to.
+    pub current: usize,
+}
+
+/// Byte ranges of the case-insensitive occurrences of `query` in `line`.
+fn find_in_line(line: &str, query: &str) -> Vec<(usize, usize)> {
+    let lower = |c: char| c.to_lowercase().next().unwrap_or(c);
+    let query: Vec<char> = query.chars().map(lower).collect();
+    if query.is_empty() {
+        return vec![];
+    }
+    let chars: Vec<(usize, char)> =
+        line.char_indices().map(|(i, c)| (i, lower(c))).collect();
+    let mut matches = vec![];
+    let mut i = 0;
+    while i + query.len() <= chars.len() {
+        if chars[i..i + query.len()]
+            .iter()
+            .zip(&query)
+            .all(|((_, c), q)| c == q)
+        {
+            let end = chars.get(i + query.len()).map_or(line.len(), |c| c.0);
+            matches.push((chars[i].0, end));
+            i += query.len();
+        } else {
+            i += 1;
+        }
+    }
+    matches
+}
+
+/// Range of content lines being selected for highlighting, in terms of
+/// the logical lines of the rendered entry.
+pub struct Highlighting {
+    pub anchor: usize,
+    pub cursor: usize,
+}
+
+impl Highlighting {
+    pub fn contains(&self, line: usize) -> bool {
+        let (start, end) = self.range();
+        (start..=end).contains(&line)
+    }
+
+    fn range(&self) -> (usize, usize) {
+        (self.anchor.min(self.cursor), self.anchor.max(self.cursor))
+    }
+}
+
+/// Which feeds the entry list shows entries from, chosen in the sidebar.
+#[derive(Clone, PartialEq)]
+pub enum FeedFilter {
+    All,
+    Tag(String),
+    /// A feed, by URL.
+    Feed(String),
+}
+
+/// The query typed after `/` to filter the entry list.
+#[derive(Default)]
+pub struct Filter {
+    pub input: String,
+    /// Set while the query is being typed.
+    pub editing: bool,
+    query: Query,
+}
+
+#[derive(Default)]
+pub struct Sidebar {
+    pub shown: bool,
+    pub list_state: ListState,
+    /// Area of the rendered sidebar list, excluding its borders, as of the
+    /// last frame.
+    pub area: Rect,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum View {
+    All,
+    Starred,
 }
 
 pub struct State {
     pub selected_entry: usize,
     pub list_state: ListState,
-    pub feeds: Vec<Feed>,
-    pub entries: Vec<EntryWithAuthor>,
+    pub feeds: Vec<CachedFeed>,
+    /// Shared with the search indexer, which reads them in the background.
+    pub entries: Arc<Vec<EntryWithAuthor>>,
+    /// Indices into `entries` of the entries shown in the list.
+    pub visible: Vec<usize>,
+    pub view: View,
+    pub sort: SortOrder,
+    pub group_by: Grouping,
+    /// Section headers shown in the entry list, each with the index into
+    /// `visible` of the first entry below it.
+    pub headers: Vec<(usize, String)>,
+    /// The feeds file, giving the feeds and tags shown in the sidebar.
+    pub subscriptions: Vec<Subscription>,
+    pub feed_filter: FeedFilter,
+    pub sidebar: Sidebar,
+    pub filter: Filter,
+    pub entry_states: EntryStates,
+    pub config: Config,
     pub render_raw_html: bool,
     pub selected_window: SelectedWindow,
     pub entry_scroll_offset: u16,
-    pub separator: Separator,
+    pub separators: Separators,
+    /// Message shown below the entry list until the next key press.
+    pub status: Option<String>,
+    /// Set when another instance holds the data directory lock, in which
+    /// case nothing is written to disk.
+    pub read_only: bool,
+    pub search_index: Arc<Mutex<SearchIndex>>,
+    pub search: Search,
+    pub highlighting: Option<Highlighting>,
+    pub find: Find,
+    /// The link of the selected entry picked by cycling through its links.
+    pub selected_link: Option<usize>,
+    pub images: Images,
+    /// Area of the rendered entry content, excluding its borders, as of the
+    /// last frame.
+    pub content_area: Rect,
+    /// Area of the rendered entry list, excluding its borders, as of the
+    /// last frame.
+    pub list_area: Rect,
+    /// Areas of the sidebar, entry list and entry content panes, including
+    /// their borders, as of the last frame.
+    pub pane_areas: Vec<(SelectedWindow, Rect)>,
+    /// Set when the note of the selected entry should be opened in an
+    /// editor, which the event loop does as it owns the terminal.
+    pub note_requested: bool,
+    /// Set when the entries or their annotations changed since the search
+    /// index was last updated.
+    pub index_dirty: bool,
+    /// Set until the cached feeds have been loaded.
+    pub loading: bool,
+    pub keymap: Keymap,
+    /// Keys pressed so far of a key sequence.
+    pub pending_keys: KeySequence,
+    /// The count typed before an action, such as the 5 of `5j`.
+    pub pending_count: Option<usize>,
+    /// The command being typed after `:`.
+    pub command_line: Option<String>,
+    /// Set when the feeds should be fetched again, which the event loop
+    /// does as it can send the fetched feeds back.
+    pub refresh_requested: bool,
+    pub quit_requested: bool,
+    selected_at: Instant,
+    /// Set once the selected entry has been marked read by dwelling on it,
+    /// or marked read or unread by hand, so that dwelling does not undo it.
+    dwell_done: bool,
 }
 
 impl State {
-    pub fn new(feeds: Vec<Feed>) -> Self {
+    pub fn new(
+        feeds: Vec<CachedFeed>,
+        entry_states: EntryStates,
+        config: Config,
+    ) -> Self {
         let selected_entry = 0;
         let mut list_state = ListState::default();
         list_state.select(Some(selected_entry));
-        let entries = entries_from_feeds(&feeds);
-        State {
+        let entries = Arc::new(entries_from_feeds(&feeds, &entry_states));
+        let images = Images::new(config.images);
+        let mut state = State {
             selected_entry,
             list_state,
             feeds,
             entries,
+            visible: vec![],
+            view: View::All,
+            sort: config.sort,
+            group_by: config.group_by,
+            headers: vec![],
+            subscriptions: vec![],
+            feed_filter: FeedFilter::All,
+            sidebar: Sidebar::default(),
+            filter: Filter::default(),
+            entry_states,
+            config,
             render_raw_html: false,
             selected_window: SelectedWindow::EntryList,
             entry_scroll_offset: 0,
-            separator: Separator::new(),
+            separators: Separators::new(),
+            status: None,
+            read_only: false,
+            search_index: Arc::default(),
+            search: Search::default(),
+            highlighting: None,
+            find: Find::default(),
+            selected_link: None,
+            images,
+            content_area: Rect::default(),
+            list_area: Rect::default(),
+            pane_areas: vec![],
+            note_requested: false,
+            index_dirty: false,
+            loading: true,
+            keymap: Keymap::default(),
+            pending_keys: vec![],
+            pending_count: None,
+            command_line: None,
+            refresh_requested: false,
+            quit_requested: false,
+            selected_at: Instant::now(),
+            dwell_done: false,
+        };
+        state.update_visible();
+        state.on_selection_changed();
+        state
+    }
+
+    pub fn selected(&self) -> Option<&EntryWithAuthor> {
+        self.visible
+            .get(self.selected_entry)
+            .map(|&i| &self.entries[i])
+    }
+
+    pub fn visible_entries(&self) -> impl Iterator<Item = &EntryWithAuthor> {
+        self.visible.iter().map(|&i| &self.entries[i])
+    }
+
+    /// The row of the entry list of a visible entry, counting the headers
+    /// above it.
+    pub fn list_row(&self, index: usize) -> usize {
+        index + self.headers.iter().take_while(|(i, _)| *i <= index).count()
+    }
+
+    /// Select a visible entry. Headers are not entries, so moving up and down
+    /// the list skips over them.
+    fn select(&mut self, index: usize) {
+        self.selected_entry = index;
+        self.list_state
+            .select(Some(self.list_row(self.selected_entry)));
+        self.entry_scroll_offset = 0;
+        self.highlighting = None;
+        self.find = Find::default();
+        self.selected_link = None;
+        self.on_selection_changed();
+    }
+
+    fn on_selection_changed(&mut self) {
+        self.selected_at = Instant::now();
+        self.dwell_done = false;
+        if self.config.mark_read == MarkRead::Select {
+            self.set_selected_read(true);
         }
     }
 
-    pub fn move_down(&mut self) {
-        match self.selected_window {
-            SelectedWindow::EntryList => {
-                if self.selected_entry < self.entries.len().saturating_sub(1) {
-                    self.selected_entry += 1;
-                    self.list_state.select(Some(self.selected_entry));
-                    self.entry_scroll_offset = 0;
-                }
+    /// Recompute the visible entries, keeping the selected entry selected
+    /// if it is still visible.
+    fn update_visible(&mut self) {
+        let previous = self.selected_entry_key();
+        self.update_visible_from(previous);
+    }
+
+    /// Rebuild the entries from the feeds and entry states. The selected
+    /// entry is looked up first, as the visible indices are then stale.
+    fn reload_entries(&mut self) {
+        let previous = self.selected_entry_key();
+        self.entries =
+            Arc::new(entries_from_feeds(&self.feeds, &self.entry_states));
+        self.update_visible_from(previous);
+    }
+
+    fn update_visible_from(&mut self, previous: Option<(String, String)>) {
+        let feed_urls = self.filtered_feed_urls();
+        let feed_infos = self.feed_infos();
+        let no_tags = vec![];
+        self.visible = (0..self.entries.len())
+            .filter(|&i| {
+                feed_urls
+                    .as_ref()
+                    .is_none_or(|urls| urls.contains(&self.entries[i].feed_url))
+            })
+            .filter(|&i| match self.view {
+                View::All => true,
+                View::Starred => self.is_starred(&self.entries[i]),
+            })
+            .filter(|&i| {
+                let entry = &self.entries[i];
+                let feed = feed_infos.get(entry.feed_url.as_str());
+                self.filter.query.is_empty()
+                    || self.filter.query.matches(
+                        entry,
+                        feed.unwrap_or(&FeedInfo {
+                            title: &entry.feed_url,
+                            tags: &no_tags,
+                        }),
+                    )
+            })
+            .collect();
+        self.sort_visible();
+        self.group_visible();
+        let index = self
+            .visible_entries()
+            .position(|e| Some(e.key()) == previous)
+            .unwrap_or_else(|| {
+                self.selected_entry
+                    .min(self.visible.len().saturating_sub(1))
+            });
+        self.selected_entry = index;
+        self.list_state
+            .select(Some(self.list_row(self.selected_entry)));
+        if self.selected_entry_key() != previous {
+            self.entry_scroll_offset = 0;
+            self.highlighting = None;
+            self.find = Find::default();
+            self.selected_link = None;
+            self.on_selection_changed();
+        }
+    }
+
+    fn sort_visible(&mut self) {
+        let entries = &self.entries;
+        match self.sort {
+            SortOrder::Published => {
+                self.visible.sort_by_key(|&i| Reverse(entries[i].date()));
             }
-            SelectedWindow::EntryContent => {
-                self.entry_scroll_offset += 1;
+            SortOrder::Updated => {
+                self.visible
+                    .sort_by_key(|&i| Reverse(entries[i].updated_date()));
+            }
+            SortOrder::Feed => {
+                let titles: HashMap<&str, String> = self
+                    .feeds
+                    .iter()
+                    .map(|f| (f.url.as_str(), self.feed_title(&f.url)))
+                    .map(|(url, title)| (url, title.to_lowercase()))
+                    .collect();
+                let title = |i: usize| {
+                    let url = entries[i].feed_url.as_str();
+                    titles.get(url).map_or(url, String::as_str)
+                };
+                self.visible.sort_by(|&a, &b| {
+                    title(a)
+                        .cmp(title(b))
+                        .then(entries[b].date().cmp(&entries[a].date()))
+                });
+            }
+            SortOrder::Title => {
+                self.visible
+                    .sort_by_cached_key(|&i| entries[i].title().to_lowercase());
+            }
+            SortOrder::Unread => {
+                let entry_states = &self.entry_states;
+                self.visible.sort_by_key(|&i| {
+                    let entry = &entries[i];
+                    let read =
+                        entry_states.is_read(&entry.feed_url, &entry.entry.id);
+                    (read, Reverse(entry.date()))
+                });
             }
-            SelectedWindow::HelpPopup => {}
         }
     }
 
-    pub fn move_up(&mut self) {
-        match self.selected_window {
+    /// Order the visible entries by group, keeping the sort order within
+    /// each group, and work out the headers.
+    fn group_visible(&mut self) {
+        let (visible, headers) = match self.group_by {
+            Grouping::None => (std::mem::take(&mut self.visible), vec![]),
+            Grouping::Date => {
+                let today = Local::now().date_naive();
+                let sort = self.sort;
+                group_entries(&self.visible, &self.entries, |entry| {
+                    date_group(entry.display_date(sort), today)
+                })
+            }
+            Grouping::Feed => {
+                group_entries(&self.visible, &self.entries, |entry| {
+                    let title = self.feed_title(&entry.feed_url).to_string();
+                    (title.to_lowercase(), title)
+                })
+            }
+        };
+        self.visible = visible;
+        self.headers = headers;
+    }
+
+    /// Switch to the next sort order, keeping the selected entry selected.
+    pub fn cycle_sort(&mut self) {
+        self.sort = self.sort.next();
+        self.update_visible();
+        *self.list_state.offset_mut() = 0;
+    }
+
+    /// Switch to the next grouping, keeping the selected entry selected.
+    pub fn cycle_grouping(&mut self) {
+        self.group_by = self.group_by.next();
+        self.update_visible();
+        *self.list_state.offset_mut() = 0;
+    }
+
+    /// Called on every iteration of the event loop to apply time-based
+    /// state changes.
+    pub fn tick(&mut self) {
+        let dwell = Duration::from_secs(self.config.mark_read_dwell_secs);
+        if self.config.mark_read == MarkRead::Dwell
+            && !self.dwell_done
+            && self.selected_at.elapsed() >= dwell
+        {
+            self.set_selected_read(true);
+            self.dwell_done = true;
+        }
+        if self.selected_window == SelectedWindow::SearchPopup
+            && self.search.indexing
+        {
+            self.run_search();
+        }
+    }
+
+    pub fn is_read(&self, entry: &EntryWithAuthor) -> bool {
+        self.entry_states.is_read(&entry.feed_url, &entry.entry.id)
+    }
+
+    pub fn is_starred(&self, entry: &EntryWithAuthor) -> bool {
+        self.entry_states
+            .is_starred(&entry.feed_url, &entry.entry.id)
+    }
+
+    fn set_selected_read(&mut self, read: bool) {
+        let Some(entry) = self.selected() else {
+            return;
+        };
+        let (feed_url, id) = entry.key();
+        if self.entry_states.set_read(&feed_url, &id, read) {
+            self.save_entry_states();
+        }
+    }
+
+    pub fn toggle_selected_read(&mut self) {
+        if let Some(entry) = self.selected() {
+            let read = self.is_read(entry);
+            self.set_selected_read(!read);
+            self.dwell_done = true;
+        }
+    }
+
+    pub fn mark_all_read(&mut self) {
+        let mut changed = false;
+        for &i in &self.visible {
+            let entry = &self.entries[i];
+            changed |= self.entry_states.set_read(
+                &entry.feed_url,
+                &entry.entry.id,
+                true,
+            );
+        }
+        if changed {
+            self.save_entry_states();
+        }
+    }
+
+    pub fn toggle_selected_starred(&mut self) {
+        let Some(entry) = self.selected() else {
+            return;
+        };
+        let starred = self.is_starred(entry);
+        let feed_url = entry.feed_url.clone();
+        let entry = entry.full_entry();
+        self.entry_states.set_starred(&feed_url, entry, !starred);
+        self.save_entry_states();
+        // Unstarring may remove an entry which is no longer in its feed
+        self.reload_entries();
+        self.index_dirty = true;
+    }
+
+    /// URLs of the feeds matching the feed filter, or `None` for every feed.
+    fn filtered_feed_urls(&self) -> Option<HashSet<String>> {
+        match &self.feed_filter {
+            FeedFilter::All => None,
+            FeedFilter::Feed(url) => Some(HashSet::from([url.clone()])),
+            FeedFilter::Tag(tag) => Some(
+                self.subscriptions
+                    .iter()
+                    .filter(|s| s.tags.contains(tag))
+                    .map(|s| s.url.clone())
+                    .collect(),
+            ),
+        }
+    }
+
+    /// The title and tags of each feed, by URL.
+    fn feed_infos(&self) -> HashMap<&str, FeedInfo<'_>> {
+        let mut infos: HashMap<&str, FeedInfo> = self
+            .feeds
+            .iter()
+            .map(|f| {
+                let title =
+                    f.feed.title.as_ref().map_or(&f.url, |t| &t.content);
+                (f.url.as_str(), FeedInfo { title, tags: &[] })
+            })
+            .collect();
+        for subscription in &self.subscriptions {
+            infos
+                .entry(&subscription.url)
+                .or_insert(FeedInfo {
+                    title: &subscription.url,
+                    tags: &[],
+                })
+                .tags = &subscription.tags;
+        }
+        infos
+    }
+
+    pub fn open_filter(&mut self) {
+        self.filter.editing = true;
+        self.selected_window = SelectedWindow::EntryList;
+    }
+
+    /// Stop typing the filter query, keeping it applied.
+    pub fn keep_filter(&mut self) {
+        self.filter.editing = false;
+    }
+
+    pub fn clear_filter(&mut self) {
+        self.filter = Filter::default();
+        self.update_visible();
+    }
+
+    pub fn push_filter_char(&mut self, c: char) {
+        self.filter.input.push(c);
+        self.filter.query = Query::parse(&self.filter.input);
+        self.update_visible();
+    }
+
+    pub fn pop_filter_char(&mut self) {
+        self.filter.input.pop();
+        self.filter.query = Query::parse(&self.filter.input);
+        self.update_visible();
+    }
+
+    /// The nodes of the sidebar: every entry, then each tag, then each
+    /// subscribed feed.
+    pub fn sidebar_nodes(&self) -> Vec<FeedFilter> {
+        let mut tags: Vec<&String> =
+            self.subscriptions.iter().flat_map(|s| &s.tags).collect();
+        tags.sort();
+        tags.dedup();
+        std::iter::once(FeedFilter::All)
+            .chain(tags.into_iter().map(|t| FeedFilter::Tag(t.clone())))
+            .chain(
+                self.subscriptions
+                    .iter()
+                    .map(|s| FeedFilter::Feed(s.url.clone())),
+            )
+            .collect()
+    }
+
+    /// The title of a feed from the cache, falling back to its URL.
+    pub fn feed_title<'a>(&'a self, url: &'a str) -> &'a str {
+        self.feeds
+            .iter()
+            .find(|f| f.url == url)
+            .and_then(|f| f.feed.title.as_ref())
+            .map_or(url, |t| t.content.as_str())
+    }
+
+    /// The number of unread entries of each feed, by URL.
+    pub fn unread_counts(&self) -> HashMap<&str, usize> {
+        let mut counts = HashMap::new();
+        for entry in self.entries.iter() {
+            if !self.is_read(entry) {
+                *counts.entry(entry.feed_url.as_str()).or_default() += 1;
+            }
+        }
+        counts
+    }
+
+    pub fn toggle_sidebar(&mut self) {
+        self.sidebar.shown = !self.sidebar.shown;
+        if self.sidebar.shown {
+            let position = (self.separators.positions[0] / 2.0).min(0.2);
+            self.separators.positions.insert(0, position);
+            let index = self
+                .sidebar_nodes()
+                .iter()
+                .position(|n| *n == self.feed_filter);
+            self.sidebar.list_state.select(index.or(Some(0)));
+            self.selected_window = SelectedWindow::Sidebar;
+        } else {
+            self.separators.positions.remove(0);
+            if self.selected_window == SelectedWindow::Sidebar {
+                self.selected_window = SelectedWindow::EntryList;
+            }
+            self.set_feed_filter(FeedFilter::All);
+        }
+    }
+
+    fn set_feed_filter(&mut self, filter: FeedFilter) {
+        if self.feed_filter != filter {
+            self.feed_filter = filter;
+            self.update_visible();
+        }
+    }
+
+    fn select_sidebar_node(&mut self, index: usize) {
+        let nodes = self.sidebar_nodes();
+        if let Some(node) = nodes.get(index) {
+            self.sidebar.list_state.select(Some(index));
+            self.set_feed_filter(node.clone());
+        }
+    }
+
+    pub fn switch_view(&mut self) {
+        self.view = match self.view {
+            View::All => View::Starred,
+            View::Starred => View::All,
+        };
+        self.update_visible();
+    }
+
+    fn save_entry_states(&self) {
+        if self.read_only {
+            return;
+        }
+        if let Err(e) = entry_state::save(&self.entry_states) {
+            log::error!("Failed to save entry state: {}", e);
+        }
+    }
+
+    /// Move the selection of the focused pane by `delta` items, or scroll
+    /// the content pane by `delta` rows.
+    pub fn move_by(&mut self, delta: isize) {
+        self.move_in(self.selected_window, delta);
+    }
+
+    /// Move the selection of a pane by `delta` items, or scroll the content
+    /// pane by `delta` rows, whether or not it is focused.
+    fn move_in(&mut self, window: SelectedWindow, delta: isize) {
+        let offset = |position: usize, len: usize| {
+            position
+                .saturating_add_signed(delta)
+                .min(len.saturating_sub(1))
+        };
+        match window {
             SelectedWindow::EntryList => {
-                if self.selected_entry > 0 {
-                    self.selected_entry -= 1;
-                    self.list_state.select(Some(self.selected_entry));
-                    self.entry_scroll_offset = 0;
+                let index = offset(self.selected_entry, self.visible.len());
+                if index != self.selected_entry {
+                    self.select(index);
                 }
             }
+            SelectedWindow::EntryContent if self.highlighting.is_some() => {
+                self.move_highlight_cursor(delta);
+            }
             SelectedWindow::EntryContent => {
-                self.entry_scroll_offset =
-                    self.entry_scroll_offset.saturating_sub(1);
+                let offset = self.entry_scroll_offset as usize;
+                self.entry_scroll_offset = offset
+                    .saturating_add_signed(delta)
+                    .min(self.last_scroll_offset())
+                    as u16;
+            }
+            SelectedWindow::Sidebar => {
+                let selected = self.sidebar.list_state.selected().unwrap_or(0);
+                let len = self.sidebar_nodes().len();
+                self.select_sidebar_node(offset(selected, len));
+            }
+            SelectedWindow::SearchPopup => {
+                let selected = self.search.list_state.selected().unwrap_or(0);
+                let len = self.search.hits.len();
+                self.search.list_state.select(Some(offset(selected, len)));
             }
             SelectedWindow::HelpPopup => {}
         }
     }
 
+    /// Select the item of the focused pane at `index`, or scroll the content
+    /// pane to that row, going no further than the last one.
+    pub fn move_to(&mut self, index: usize) {
+        let current = match self.selected_window {
+            SelectedWindow::EntryList => self.selected_entry,
+            SelectedWindow::EntryContent => match &self.highlighting {
+                Some(highlighting) => highlighting.cursor,
+                None => self.entry_scroll_offset as usize,
+            },
+            SelectedWindow::Sidebar => {
+                self.sidebar.list_state.selected().unwrap_or(0)
+            }
+            SelectedWindow::SearchPopup => {
+                self.search.list_state.selected().unwrap_or(0)
+            }
+            SelectedWindow::HelpPopup => return,
+        };
+        let last = match self.selected_window {
+            SelectedWindow::EntryContent if self.highlighting.is_none() => {
+                self.last_scroll_offset()
+            }
+            _ => usize::MAX,
+        };
+        let target = index.min(last).min(isize::MAX as usize);
+        let delta = target as isize - current as isize;
+        self.move_by(delta);
+    }
+
+    /// The number of items of the focused pane which fit on screen, or the
+    /// number of rows for the content pane.
+    pub fn page_size(&self) -> usize {
+        let size = match self.selected_window {
+            SelectedWindow::EntryContent => self.content_area.height as usize,
+            SelectedWindow::Sidebar => self.sidebar.area.height as usize,
+            // Entries and search hits take up two rows each
+            SelectedWindow::SearchPopup => self.search.area.height as usize / 2,
+            _ => self.list_area.height as usize / 2,
+        };
+        size.max(1)
+    }
+
     pub fn move_left(&mut self) {
         match self.selected_window {
-            SelectedWindow::HelpPopup => {}
-            SelectedWindow::EntryContent | SelectedWindow::EntryList => {
+            SelectedWindow::HelpPopup | SelectedWindow::SearchPopup => {}
+            SelectedWindow::EntryList | SelectedWindow::Sidebar
+                if self.sidebar.shown =>
+            {
+                self.selected_window = SelectedWindow::Sidebar;
+            }
+            SelectedWindow::EntryContent
+            | SelectedWindow::EntryList
+            | SelectedWindow::Sidebar => {
                 self.selected_window = SelectedWindow::EntryList;
+                self.highlighting = None;
             }
         }
     }
 
     pub fn move_right(&mut self) {
         match self.selected_window {
-            SelectedWindow::HelpPopup => {}
+            SelectedWindow::HelpPopup | SelectedWindow::SearchPopup => {}
+            SelectedWindow::Sidebar => {
+                self.selected_window = SelectedWindow::EntryList;
+            }
             SelectedWindow::EntryContent | SelectedWindow::EntryList => {
-                self.selected_window = SelectedWindow::EntryContent;
+                self.focus_content();
+            }
+        }
+    }
+
+    fn focus_content(&mut self) {
+        self.selected_window = SelectedWindow::EntryContent;
+        if self.config.mark_read == MarkRead::Open {
+            self.set_selected_read(true);
+        }
+    }
+
+    /// The pane at a position of the screen, if any.
+    fn pane_at(&self, column: u16, row: u16) -> Option<SelectedWindow> {
+        self.pane_areas
+            .iter()
+            .find(|(_, area)| area.contains(Position::new(column, row)))
+            .map(|(window, _)| *window)
+    }
+
+    /// The visible entry shown at a row of the screen, if any. Entries take
+    /// up two rows each and group headers one, starting from the first item
+    /// scrolled into view.
+    fn entry_at_row(&self, row: u16) -> Option<usize> {
+        let area = self.list_area;
+        let offset = self.list_state.offset();
+        let mut y = area.y;
+        let mut item = 0;
+        let mut headers = self.headers.iter().peekable();
+        for index in 0..self.visible.len() {
+            if y >= area.bottom() || row < y {
+                break;
+            }
+            if headers.next_if(|(i, _)| *i == index).is_some() {
+                if item >= offset {
+                    if row == y {
+                        return None;
+                    }
+                    y += 1;
+                }
+                item += 1;
             }
+            if item >= offset {
+                if row < y + 2 {
+                    return Some(index);
+                }
+                y += 2;
+            }
+            item += 1;
+        }
+        None
+    }
+
+    /// Scroll the pane under the pointer by `delta` items, or by a few rows
+    /// at a time for the content pane. The open popup, if any, is scrolled
+    /// instead.
+    pub fn scroll_at(&mut self, column: u16, row: u16, delta: isize) {
+        match self.selected_window {
+            SelectedWindow::HelpPopup => {}
+            SelectedWindow::SearchPopup => self.move_by(delta),
+            _ => match self.pane_at(column, row) {
+                Some(SelectedWindow::EntryContent) => self.move_in(
+                    SelectedWindow::EntryContent,
+                    delta * CONTENT_SCROLL_ROWS,
+                ),
+                Some(window) => self.move_in(window, delta),
+                None => {}
+            },
+        }
+    }
+
+    /// Focus the pane at a position of the screen, selecting the entry or
+    /// sidebar item under the pointer.
+    pub fn click_at(&mut self, column: u16, row: u16) {
+        if matches!(
+            self.selected_window,
+            SelectedWindow::HelpPopup | SelectedWindow::SearchPopup
+        ) {
+            return;
+        }
+        match self.pane_at(column, row) {
+            Some(SelectedWindow::EntryList) => {
+                self.selected_window = SelectedWindow::EntryList;
+                self.highlighting = None;
+                if let Some(index) = self.entry_at_row(row)
+                    && index != self.selected_entry
+                {
+                    self.select(index);
+                }
+            }
+            Some(SelectedWindow::EntryContent)
+                if self.selected_window != SelectedWindow::EntryContent =>
+            {
+                self.focus_content();
+            }
+            Some(SelectedWindow::Sidebar) => {
+                self.selected_window = SelectedWindow::Sidebar;
+                self.highlighting = None;
+                let top = self.sidebar.area.y;
+                if row >= top {
+                    let index =
+                        (row - top) as usize + self.sidebar.list_state.offset();
+                    self.select_sidebar_node(index);
+                }
+            }
+            _ => {}
         }
     }
 
-    pub fn update_feeds(&mut self, feeds: Vec<Feed>) {
+    pub fn update_feeds(&mut self, feeds: Vec<CachedFeed>) {
         self.feeds = feeds;
-        self.entries = entries_from_feeds(&self.feeds);
-        if self.selected_entry >= self.entries.len() {
-            self.selected_entry = self.entries.len().saturating_sub(1);
+        self.reload_entries();
+        self.index_dirty = true;
+    }
+
+    pub fn selected_note(&self) -> Option<&str> {
+        let entry = self.selected()?;
+        self.entry_states.note(&entry.feed_url, &entry.entry.id)
+    }
+
+    pub fn selected_highlights(&self) -> &[Highlight] {
+        match self.selected() {
+            Some(entry) => self
+                .entry_states
+                .highlights(&entry.feed_url, &entry.entry.id),
+            None => &[],
+        }
+    }
+
+    pub fn request_note_edit(&mut self) {
+        if self.selected().is_some() {
+            self.note_requested = true;
+        }
+    }
+
+    pub fn set_selected_note(&mut self, note: String) {
+        let Some((feed_url, id)) = self.selected_entry_key() else {
+            return;
+        };
+        self.entry_states.set_note(&feed_url, &id, note);
+        self.save_entry_states();
+        self.index_dirty = true;
+    }
+
+    /// The text of each logical line of the rendered content of the selected
+    /// entry.
+    fn content_lines(&self) -> Vec<String> {
+        self.selected_document()
+            .lines
+            .iter()
+            .map(|line| line.spans.iter().map(|s| s.content.as_ref()).collect())
+            .collect()
+    }
+
+    /// The row at which each logical content line starts once wrapped to the
+    /// width of the content area.
+    fn content_line_rows(&self) -> Vec<usize> {
+        let mut row = 0;
+        let mut rows = vec![];
+        for line in self.content_lines() {
+            rows.push(row);
+            row += self.wrapped_height(line).max(1);
+        }
+        rows
+    }
+
+    /// The number of rows `text` takes up once wrapped to the width of the
+    /// content area.
+    fn wrapped_height(&self, text: String) -> usize {
+        Paragraph::new(text)
+            .wrap(Wrap { trim: false })
+            .line_count(self.content_area.width.max(1))
+    }
+
+    /// Start selecting lines to highlight, from the first line on screen.
+    pub fn start_highlighting(&mut self) {
+        if self.selected_window != SelectedWindow::EntryContent
+            || self.render_raw_html
+        {
+            return;
+        }
+        let rows = self.content_line_rows();
+        if rows.is_empty() {
+            return;
+        }
+        let offset = self.entry_scroll_offset as usize;
+        let first = rows
+            .iter()
+            .position(|&row| row >= offset)
+            .unwrap_or(rows.len() - 1);
+        self.highlighting = Some(Highlighting {
+            anchor: first,
+            cursor: first,
+        });
+    }
+
+    /// Move the end of the highlighted range by `delta` lines, scrolling to
+    /// keep it on screen.
+    fn move_highlight_cursor(&mut self, delta: isize) {
+        let rows = self.content_line_rows();
+        let Some(highlighting) = &mut self.highlighting else {
+            return;
+        };
+        highlighting.cursor = highlighting
+            .cursor
+            .saturating_add_signed(delta)
+            .min(rows.len().saturating_sub(1));
+        if let Some(&row) = rows.get(highlighting.cursor) {
+            self.scroll_to_row(row);
         }
-        self.list_state.select(Some(self.selected_entry));
+    }
+
+    /// The number of rows of the content once wrapped, which is the raw
+    /// HTML when it is shown instead.
+    fn content_height(&self) -> usize {
+        if self.render_raw_html {
+            return self.wrapped_height(self.get_selected_entry_body().into());
+        }
+        let lines = self.content_lines();
+        let rows = self.content_line_rows();
+        match (lines.last(), rows.last()) {
+            (Some(line), Some(row)) => {
+                row + self.wrapped_height(line.clone()).max(1)
+            }
+            _ => 0,
+        }
+    }
+
+    /// The furthest the content pane can be scrolled, leaving the end of the
+    /// content at the bottom of the pane.
+    fn last_scroll_offset(&self) -> usize {
+        let height = self.content_area.height as usize;
+        self.content_height()
+            .saturating_sub(height)
+            .min(u16::MAX as usize)
+    }
+
+    /// Scroll the content pane as little as possible for `row` to be visible.
+    fn scroll_to_row(&mut self, row: usize) {
+        let height = self.content_area.height.max(1) as usize;
+        let offset = self.entry_scroll_offset as usize;
+        if row < offset {
+            self.entry_scroll_offset = row as u16;
+        } else if row >= offset + height {
+            self.entry_scroll_offset = (row + 1 - height) as u16;
+        }
+    }
+
+    /// Whether a prompt is open, in which case keys are typed into it.
+    pub fn is_typing(&self) -> bool {
+        self.selected_window == SelectedWindow::SearchPopup
+            || self.command_line.is_some()
+            || self.find.editing
+            || self.filter.editing
+    }
+
+    /// Whether a find is active in the content pane, in which case `n` and
+    /// `N` move between its matches.
+    pub fn is_finding(&self) -> bool {
+        self.selected_window == SelectedWindow::EntryContent
+            && !self.find.input.is_empty()
+    }
+
+    pub fn open_find(&mut self) {
+        if self.render_raw_html {
+            return;
+        }
+        self.find.editing = true;
+    }
+
+    /// Stop typing the find query, keeping its matches highlighted.
+    pub fn keep_find(&mut self) {
+        self.find.editing = false;
+    }
+
+    pub fn clear_find(&mut self) {
+        self.find = Find::default();
+    }
+
+    pub fn push_find_char(&mut self, c: char) {
+        self.find.input.push(c);
+        self.update_find();
+    }
+
+    pub fn pop_find_char(&mut self) {
+        self.find.input.pop();
+        self.update_find();
+    }
+
+    /// Find the matches of the query, and jump to the first one on or after
+    /// the top of the pane.
+    fn update_find(&mut self) {
+        let lines = self.content_lines();
+        self.find.matches = lines
+            .iter()
+            .enumerate()
+            .flat_map(|(i, line)| {
+                find_in_line(line, &self.find.input).into_iter().map(
+                    move |(start, end)| FindMatch {
+                        line: i,
+                        start,
+                        end,
+                    },
+                )
+            })
+            .collect();
+        let offset = self.entry_scroll_offset as usize;
+        let rows = self.content_line_rows();
+        self.find.current = self
+            .find
+            .matches
+            .iter()
+            .position(|m| self.match_row(m, &lines, &rows) >= offset)
+            .unwrap_or(0);
+        self.scroll_to_match();
+    }
+
+    /// The row on which a match is shown once lines are wrapped.
+    fn match_row(
+        &self,
+        m: &FindMatch,
+        lines: &[String],
+        rows: &[usize],
+    ) -> usize {
+        let before = lines[m.line][..m.end].to_string();
+        rows[m.line] + self.wrapped_height(before).saturating_sub(1)
+    }
+
+    fn scroll_to_match(&mut self) {
+        let Some(m) = self.find.matches.get(self.find.current) else {
+            return;
+        };
+        let lines = self.content_lines();
+        let rows = self.content_line_rows();
+        let row = self.match_row(m, &lines, &rows);
+        self.scroll_to_row(row);
+    }
+
+    pub fn next_match(&mut self) {
+        if !self.find.matches.is_empty() {
+            self.find.current =
+                (self.find.current + 1) % self.find.matches.len();
+            self.scroll_to_match();
+        }
+    }
+
+    pub fn previous_match(&mut self) {
+        let count = self.find.matches.len();
+        if count > 0 {
+            self.find.current = (self.find.current + count - 1) % count;
+            self.scroll_to_match();
+        }
+    }
+
+    /// Highlight the selected lines, or remove the highlight they fall in.
+    pub fn finish_highlighting(&mut self) {
+        let Some(highlighting) = self.highlighting.take() else {
+            return;
+        };
+        let Some((feed_url, id)) = self.selected_entry_key() else {
+            return;
+        };
+        let (start, end) = highlighting.range();
+        let lines = self.content_lines();
+        // The content may have been rewrapped since highlighting started
+        let Some(last) = lines.len().checked_sub(1) else {
+            return;
+        };
+        if start > last {
+            return;
+        }
+        let end = end.min(last);
+        if lines[start..=end].iter().all(|line| line.trim().is_empty()) {
+            return;
+        }
+        self.entry_states.toggle_highlight(
+            &feed_url,
+            &id,
+            &lines,
+            (start, end),
+        );
+        self.save_entry_states();
+        self.index_dirty = true;
+    }
+
+    pub fn cancel_highlighting(&mut self) {
+        self.highlighting = None;
+    }
+
+    fn selected_entry_key(&self) -> Option<(String, String)> {
+        self.selected().map(EntryWithAuthor::key)
     }
 
     pub fn get_selected_entry_body(&self) -> &str {
-        self.entries[self.selected_entry]
-            .entry
-            .content
-            .as_ref()
-            .and_then(|c| c.body.as_deref())
+        self.selected()
+            .and_then(EntryWithAuthor::content)
             .unwrap_or("No Content")
     }
 
+    pub fn open_search(&mut self) {
+        self.selected_window = SelectedWindow::SearchPopup;
+        self.run_search();
+    }
+
+    pub fn close_search(&mut self) {
+        self.selected_window = SelectedWindow::EntryList;
+    }
+
+    pub fn push_search_char(&mut self, c: char) {
+        self.search.query.push(c);
+        self.run_search();
+    }
+
+    pub fn pop_search_char(&mut self) {
+        self.search.query.pop();
+        self.run_search();
+    }
+
+    fn run_search(&mut self) {
+        let Ok(index) = self.search_index.try_lock() else {
+            self.search.indexing = true;
+            return;
+        };
+        self.search.indexing = false;
+        let results = index.query(&self.search.query, SEARCH_LIMIT);
+        drop(index);
+        let query = &self.search.query;
+        let positions: HashMap<(String, String), usize> = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| (entry.key(), i))
+            .collect();
+        self.search.hits = results
+            .into_iter()
+            .filter_map(|result| {
+                let entry = &self.entries[*positions.get(&result.key)?];
+                let text = search::html_to_text(entry.body().unwrap_or(""));
+                let (snippet, highlights) =
+                    search::snippet(&text, query, SNIPPET_WIDTH);
+                Some(SearchHit {
+                    key: result.key,
+                    title: entry.title().to_string(),
+                    snippet,
+                    highlights,
+                })
+            })
+            .collect();
+        self.search
+            .list_state
+            .select((!self.search.hits.is_empty()).then_some(0));
+    }
+
+    /// Show the selected search result in the entry list.
+    pub fn open_search_hit(&mut self) {
+        let Some(hit) = self
+            .search
+            .list_state
+            .selected()
+            .and_then(|i| self.search.hits.get(i))
+        else {
+            return;
+        };
+        let key = hit.key.clone();
+        self.view = View::All;
+        self.update_visible();
+        let position = self.visible_entries().position(|e| e.key() == key);
+        if let Some(index) = position {
+            self.select(index);
+        }
+        self.close_search();
+    }
+
     pub fn switch_render_mode(&mut self) {
         self.render_raw_html = !self.render_raw_html;
+        self.entry_scroll_offset = 0;
+        self.highlighting = None;
+        // Links are numbered differently in the raw HTML
+        self.selected_link = None;
     }
 
-    pub fn open_selected_entry_link(&self) {
-        if let Some(link) = self.entries[self.selected_entry]
-            .entry
-            .links
-            .first()
-            .map(|l| l.href.clone())
-            && let Err(e) = open::that_detached(link)
-        {
+    /// The rendered content of the selected entry.
+    pub fn selected_document(&self) -> Document {
+        let base = self
+            .selected()
+            .and_then(|e| e.entry.links.first())
+            .map(|l| l.href.as_str());
+        let width = self.content_area.width;
+        render::render_document(
+            self.get_selected_entry_body(),
+            base,
+            width,
+            &|url| self.images.size(url, width),
+        )
+    }
+
+    /// Open link `number` of the selected entry's content if given, or else
+    /// the link picked by cycling through them, or else the entry itself.
+    pub fn open_selected_entry_link(&mut self, number: Option<usize>) {
+        let link = match number.or(self.selected_link.map(|i| i + 1)) {
+            Some(number) => {
+                let links = self.selected_document().links;
+                match number.checked_sub(1).and_then(|i| links.get(i)) {
+                    Some(link) => link.url.clone(),
+                    None => {
+                        self.status = Some(format!("No link {}", number));
+                        return;
+                    }
+                }
+            }
+            None => match self.selected().and_then(|e| e.entry.links.first()) {
+                Some(link) => link.href.clone(),
+                None => return,
+            },
+        };
+        if self.config.mark_read == MarkRead::Open {
+            self.set_selected_read(true);
+        }
+        if let Err(e) = open::that_detached(link) {
             log::error!("Failed to open link: {}", e);
         }
     }
-}
 
-impl std::ops::Deref for State {
-    type Target = Vec<EntryWithAuthor>;
-
-    fn deref(&self) -> &Self::Target {
-        &self.entries
+    /// Pick the link `delta` links after the one picked, wrapping around,
+    /// and scroll it into view.
+    pub fn cycle_link(&mut self, delta: isize) {
+        let links = self.selected_document().links;
+        if links.is_empty() {
+            self.status = Some("No links".to_string());
+            return;
+        }
+        let len = links.len() as isize;
+        let index = match self.selected_link {
+            Some(index) => (index as isize + delta).rem_euclid(len),
+            None if delta > 0 => (delta - 1).rem_euclid(len),
+            None => delta.rem_euclid(len),
+        } as usize;
+        let link = &links[index];
+        self.selected_link = Some(index);
+        self.status =
+            Some(format!("{} {}", render::link_marker(index), link.url));
+        if !self.render_raw_html
+            && let Some(&row) = self.content_line_rows().get(link.line)
+        {
+            self.scroll_to_row(row);
+        }
     }
 }
 
+#[derive(Clone)]
 pub struct EntryWithAuthor {
     pub entry: Entry,
     pub author: String,
+    pub feed_url: String,
+    /// Where the body is read from if it was left on disk.
+    bodies: Bodies,
+    loaded_body: Arc<OnceLock<Body>>,
+    /// When the entry was first fetched, if it is still in its feed.
+    first_seen: Option<DateTime<Utc>>,
 }
 
 impl EntryWithAuthor {
-    pub fn new(entry: Entry, author: Option<String>) -> Self {
+    pub fn new(entry: Entry, author: Option<String>, feed_url: String) -> Self {
         let author = if let Some(author) = entry.authors.first() {
             author.name.clone()
         } else if let Some(contributor) = entry.contributors.first() {
@@ -147,46 +1370,272 @@ impl EntryWithAuthor {
         } else {
             "Unknown Author".to_string()
         };
-        EntryWithAuthor { entry, author }
+        EntryWithAuthor {
+            entry,
+            author,
+            feed_url,
+            bodies: Bodies::default(),
+            loaded_body: Arc::default(),
+            first_seen: None,
+        }
+    }
+
+    pub fn with_bodies(mut self, bodies: Bodies) -> Self {
+        self.bodies = bodies;
+        self
+    }
+
+    pub fn with_first_seen(
+        mut self,
+        first_seen: Option<DateTime<Utc>>,
+    ) -> Self {
+        self.first_seen = first_seen;
+        self
+    }
+
+    /// The publication date, see `feed::entry_date`.
+    pub fn date(&self) -> Option<DateTime<Utc>> {
+        feed::entry_date(&self.entry, self.first_seen)
+    }
+
+    /// The date the entry was last updated, falling back in the same way as
+    /// `date` otherwise.
+    pub fn updated_date(&self) -> Option<DateTime<Utc>> {
+        self.entry
+            .updated
+            .or(self.entry.published)
+            .or(self.first_seen)
+    }
+
+    /// The date shown with the entry, which is the one it is sorted by.
+    pub fn display_date(&self, sort: SortOrder) -> Option<DateTime<Utc>> {
+        match sort {
+            SortOrder::Updated => self.updated_date(),
+            _ => self.date(),
+        }
+    }
+
+    /// The content, summary and media, read from disk the first time they
+    /// are needed.
+    fn loaded_body(&self) -> Option<&Body> {
+        if !self.bodies.contains(&self.entry.id) {
+            return None;
+        }
+        Some(self.loaded_body.get_or_init(|| {
+            self.bodies.get(&self.entry.id).unwrap_or_default()
+        }))
+    }
+
+    fn parts(&self) -> (Option<&Content>, Option<&Text>) {
+        match self.loaded_body() {
+            Some(body) => (body.content.as_ref(), body.summary.as_ref()),
+            None => (self.entry.content.as_ref(), self.entry.summary.as_ref()),
+        }
+    }
+
+    /// The entry with its body filled in.
+    pub fn full_entry(&self) -> Entry {
+        let mut entry = self.entry.clone();
+        if let Some(body) = self.loaded_body() {
+            entry.content = body.content.clone();
+            entry.summary = body.summary.clone();
+            entry.media = body.media.clone();
+        }
+        entry
+    }
+
+    pub fn content(&self) -> Option<&str> {
+        self.parts().0.and_then(|c| c.body.as_deref())
+    }
+
+    pub fn title(&self) -> &str {
+        self.entry
+            .title
+            .as_ref()
+            .map(|t| t.content.as_str())
+            .unwrap_or("No Title")
+    }
+
+    /// The entry content, falling back to its summary.
+    pub fn body(&self) -> Option<&str> {
+        let (content, summary) = self.parts();
+        content
+            .and_then(|c| c.body.as_deref())
+            .or(summary.map(|s| s.content.as_str()))
+    }
+
+    /// The body as `body` returns it, but read from disk without being kept
+    /// if it has not been loaded, for when every entry is read once.
+    pub fn body_unloaded(&self) -> Option<Cow<'_, str>> {
+        if self.bodies.contains(&self.entry.id)
+            && self.loaded_body.get().is_none()
+        {
+            let body = self.bodies.get(&self.entry.id)?;
+            return body.text().map(|text| Cow::Owned(text.to_string()));
+        }
+        self.body().map(Cow::Borrowed)
+    }
+
+    /// The feed URL and entry id, which together identify an entry.
+    pub fn key(&self) -> (String, String) {
+        (self.feed_url.clone(), self.entry.id.clone())
     }
 }
 
-fn entries_from_feeds(feeds: &Vec<Feed>) -> Vec<EntryWithAuthor> {
+pub fn entries_from_feeds(
+    feeds: &Vec<CachedFeed>,
+    entry_states: &EntryStates,
+) -> Vec<EntryWithAuthor> {
     let mut entries = vec![];
-    for feed in feeds {
+    for CachedFeed {
+        url,
+        feed,
+        bodies,
+        first_seen,
+    } in feeds
+    {
         for entry in &feed.entries {
-            entries.push(EntryWithAuthor::new(
-                entry.clone(),
-                feed.authors.first().map(|a| a.name.clone()),
-            ));
+            entries.push(
+                EntryWithAuthor::new(
+                    entry.clone(),
+                    feed.authors.first().map(|a| a.name.clone()),
+                    url.clone(),
+                )
+                .with_bodies(bodies.clone())
+                .with_first_seen(first_seen.get(&entry.id).copied()),
+            );
         }
     }
-    entries.sort_by_key(|e| std::cmp::Reverse(e.entry.updated));
+    // Starred entries are kept after they drop out of the upstream feed
+    for (url, entry) in entry_states.starred_entries() {
+        let feed = feeds.iter().find(|f| f.url == url);
+        if feed.is_some_and(|f| f.feed.entries.iter().any(|e| e.id == entry.id))
+        {
+            continue;
+        }
+        entries.push(EntryWithAuthor::new(
+            entry.clone(),
+            feed.and_then(|f| f.feed.authors.first().map(|a| a.name.clone())),
+            url.to_string(),
+        ));
+    }
+    entries.sort_by_key(|e| Reverse(e.date()));
     entries
 }
 
-pub struct Separator {
-    pub mouse_down: bool,
-    pub position: f32, // position as a fraction of total width
+/// Stably sort `visible` by the group key of each entry, returning the
+/// sorted entries along with a header before the first entry of each group.
+fn group_entries<K: Ord>(
+    visible: &[usize],
+    entries: &[EntryWithAuthor],
+    group: impl Fn(&EntryWithAuthor) -> (K, String),
+) -> (Vec<usize>, Vec<(usize, String)>) {
+    let mut keyed: Vec<(K, String, usize)> = visible
+        .iter()
+        .map(|&i| {
+            let (key, label) = group(&entries[i]);
+            (key, label, i)
+        })
+        .collect();
+    keyed.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut sorted = vec![];
+    let mut headers: Vec<(usize, String)> = vec![];
+    for (_, label, i) in keyed {
+        if headers.last().is_none_or(|(_, last)| *last != label) {
+            headers.push((sorted.len(), label));
+        }
+        sorted.push(i);
+    }
+    (sorted, headers)
 }
 
-impl Separator {
+/// The date group of an entry, as a key ordering newer groups first and
+/// undated entries last, and its header.
+fn date_group(
+    date: Option<DateTime<Utc>>,
+    today: NaiveDate,
+) -> (Reverse<Option<NaiveDate>>, String) {
+    let Some(date) = date else {
+        return (Reverse(None), "Undated".to_string());
+    };
+    let day = date.with_timezone(&Local).date_naive();
+    let yesterday = today - Days::new(1);
+    let week_start =
+        today - Days::new(today.weekday().num_days_from_monday() as u64);
+    let (start, label) = if day >= today {
+        (today, "Today".to_string())
+    } else if day == yesterday {
+        (yesterday, "Yesterday".to_string())
+    } else if day >= week_start {
+        (week_start, "This week".to_string())
+    } else {
+        let month = day.with_day(1).unwrap_or(day);
+        let format = if day.year() == today.year() {
+            "%B"
+        } else {
+            "%B %Y"
+        };
+        (month, month.format(format).to_string())
+    };
+    (Reverse(Some(start)), label)
+}
+
+/// Minimum width of a pane when dragging the dividers between panes.
+const MIN_PANE_WIDTH: f32 = 20.0;
+
+/// The dividers between the panes, which can be dragged with the mouse.
+pub struct Separators {
+    /// Index of the divider being dragged.
+    pub dragging: Option<usize>,
+    /// Position of each divider from left to right, as a fraction of the
+    /// total width.
+    pub positions: Vec<f32>,
+}
+
+impl Separators {
     pub fn new() -> Self {
-        Separator {
-            mouse_down: false,
-            position: 0.5,
+        Separators {
+            dragging: None,
+            positions: vec![0.5],
         }
     }
 
-    pub fn mouse_on_separator(&self, mouse_x: u16, total_width: u16) -> bool {
-        let separator_x = (self.position * total_width as f32) as u16;
-        mouse_x.saturating_add(2) >= separator_x
-            && mouse_x.saturating_sub(1) <= separator_x
+    /// The divider under the mouse, if any.
+    pub fn divider_at(&self, mouse_x: u16, total_width: u16) -> Option<usize> {
+        self.positions.iter().position(|&position| {
+            let separator_x = (position * total_width as f32) as u16;
+            mouse_x.saturating_add(2) >= separator_x
+                && mouse_x.saturating_sub(1) <= separator_x
+        })
+    }
+
+    /// Move a divider to the mouse, keeping every pane at least
+    /// `MIN_PANE_WIDTH` wide.
+    pub fn update_position(
+        &mut self,
+        index: usize,
+        mouse_x: u16,
+        total_width: u16,
+    ) {
+        let limit = MIN_PANE_WIDTH / total_width as f32;
+        let lower =
+            index.checked_sub(1).map_or(0.0, |i| self.positions[i]) + limit;
+        let upper =
+            self.positions.get(index + 1).copied().unwrap_or(1.0) - limit;
+        let position = mouse_x as f32 / total_width as f32;
+        if lower <= upper {
+            self.positions[index] = position.clamp(lower, upper);
+        }
     }
 
-    pub fn update_position(&mut self, mouse_x: u16, total_width: u16) {
-        self.position = mouse_x as f32 / total_width as f32;
-        let limit = 40.0 / total_width as f32;
-        self.position = self.position.clamp(limit, 1.0 - limit);
+    /// The width of each pane as a percentage of the total width.
+    pub fn percentages(&self) -> Vec<u16> {
+        let mut edges = vec![0.0];
+        edges.extend(&self.positions);
+        edges.push(1.0);
+        edges
+            .windows(2)
+            .map(|w| ((w[1] - w[0]) * 100.0) as u16)
+            .collect()
     }
 }
@@ -0,0 +1,147 @@
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static SAVE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Upgrades the data of a file from one schema version to the next.
+pub type Migration = fn(Value) -> Result<Value, String>;
+
+/// On-disk files are wrapped in an envelope recording their schema version.
+/// Files written before versioning was introduced have no envelope and are
+/// treated as version 1.
+#[derive(Serialize)]
+struct Envelope<'a, T> {
+    version: u32,
+    data: &'a T,
+}
+
+fn invalid_data<E>(e: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+fn split_envelope(value: Value) -> (u32, Value) {
+    match value {
+        Value::Object(mut map)
+            if map.len() == 2 && map.contains_key("data") =>
+        {
+            let version = map.get("version").and_then(Value::as_u64);
+            match version {
+                Some(version) => {
+                    (version as u32, map.remove("data").unwrap_or_default())
+                }
+                None => (1, Value::Object(map)),
+            }
+        }
+        value => (1, value),
+    }
+}
+
+fn backup_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Load a versioned file, migrating it to `version` if it is older.
+/// `migrations[i]` upgrades version `i + 1` to version `i + 2`. The original
+/// file is backed up before it is migrated, and a file which cannot be read
+/// is moved aside so that it is not overwritten by the next save. A file
+/// written by a newer version is left in place, with an `Unsupported` error.
+/// When `read_only`, the file is migrated in memory only and never moved.
+pub fn load<T: DeserializeOwned + Serialize>(
+    path: &Path,
+    version: u32,
+    migrations: &[Migration],
+    read_only: bool,
+) -> Result<T, io::Error> {
+    debug_assert_eq!(migrations.len() + 1, version as usize);
+    let content = std::fs::read_to_string(path)?;
+    match parse(&content, version, migrations) {
+        Ok((data, file_version)) => {
+            if file_version < version && !read_only {
+                let backup =
+                    backup_path(path, &format!("v{}.bak", file_version));
+                std::fs::copy(path, &backup)?;
+                log::info!(
+                    "Migrated {} from version {} to {}, backup at {}",
+                    path.display(),
+                    file_version,
+                    version,
+                    backup.display()
+                );
+                save(path, version, &data)?;
+            }
+            Ok(data)
+        }
+        Err(e) if read_only || e.kind() == io::ErrorKind::Unsupported => Err(
+            io::Error::new(e.kind(), format!("{}: {}", path.display(), e)),
+        ),
+        Err(e) => {
+            let backup = backup_path(path, "corrupt");
+            std::fs::rename(path, &backup)?;
+            Err(invalid_data(format!(
+                "{}: {} (moved to {})",
+                path.display(),
+                e,
+                backup.display()
+            )))
+        }
+    }
+}
+
+fn parse<T: DeserializeOwned>(
+    content: &str,
+    version: u32,
+    migrations: &[Migration],
+) -> Result<(T, u32), io::Error> {
+    let value: Value = serde_json::from_str(content).map_err(invalid_data)?;
+    let (file_version, mut data) = split_envelope(value);
+    if file_version > version {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "written by a newer version of remy (version {}, expected \
+                 at most {})",
+                file_version, version
+            ),
+        ));
+    }
+    if file_version == 0 {
+        return Err(invalid_data("invalid version 0"));
+    }
+    for migration in &migrations[file_version as usize - 1..] {
+        data = migration(data).map_err(invalid_data)?;
+    }
+    let data = serde_json::from_value(data).map_err(invalid_data)?;
+    Ok((data, file_version))
+}
+
+/// A path next to `path` to write to before renaming it into place. It is
+/// unique per process and per call so that concurrent writers never share
+/// one.
+pub fn temp_path(path: &Path) -> PathBuf {
+    let count = SAVE_COUNT.fetch_add(1, Ordering::Relaxed);
+    path.with_extension(format!("{}.{}.tmp", std::process::id(), count))
+}
+
+/// Atomically write `data` to `path` with the given schema version.
+pub fn save<T: Serialize>(
+    path: &Path,
+    version: u32,
+    data: &T,
+) -> Result<(), io::Error> {
+    let json = serde_json::to_string(&Envelope { version, data })
+        .map_err(invalid_data)?;
+    let temp_path = temp_path(path);
+    std::fs::write(&temp_path, json)?;
+    std::fs::rename(temp_path, path)?;
+    Ok(())
+}
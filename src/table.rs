@@ -0,0 +1,304 @@
+use ratatui::layout::Alignment;
+use ratatui::style::Stylize;
+use ratatui::text::{Line, Span};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Columns are not made narrower than their longest word, up to this width,
+/// beyond which words are broken.
+const MAX_MIN_COLUMN_WIDTH: usize = 16;
+
+/// A cell of an HTML table, with its content before it is wrapped to the
+/// width of its column.
+#[derive(Default)]
+pub struct Cell {
+    pub lines: Vec<Line<'static>>,
+    /// Set for `<th>` cells and those of `<thead>`.
+    pub header: bool,
+    pub align: Alignment,
+}
+
+/// Lay out the rows of a table in `width` columns, with borders, or as a
+/// list of the cells of each row if its columns cannot fit side by side.
+pub fn render_table(rows: Vec<Vec<Cell>>, width: usize) -> Vec<Line<'static>> {
+    let rows: Vec<Vec<Cell>> = rows
+        .into_iter()
+        .filter(|row| !row.is_empty())
+        .map(|row| row.into_iter().map(trim_cell).collect())
+        .collect();
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+    if columns == 0 {
+        return vec![];
+    }
+    let mut natural = vec![0; columns];
+    let mut minimum = vec![1; columns];
+    for row in &rows {
+        for (column, cell) in row.iter().enumerate() {
+            for line in &cell.lines {
+                natural[column] = natural[column].max(line.width());
+                let longest_word = line_text(line)
+                    .split_whitespace()
+                    .map(UnicodeWidthStr::width)
+                    .max()
+                    .unwrap_or(0);
+                minimum[column] =
+                    minimum[column].max(longest_word.min(MAX_MIN_COLUMN_WIDTH));
+            }
+        }
+    }
+    for (natural, minimum) in natural.iter_mut().zip(&minimum) {
+        *natural = (*natural).max(*minimum);
+    }
+    // A border on either side of each column, and a space of padding
+    let available = width.saturating_sub(3 * columns + 1);
+    if minimum.iter().sum::<usize>() > available {
+        return stacked(rows, width);
+    }
+    let widths = column_widths(&natural, &minimum, available);
+    bordered(rows, &widths)
+}
+
+/// Widen the columns from their minimum widths towards their natural ones,
+/// in proportion to how much they would be widened, until they fill
+/// `available` columns.
+fn column_widths(
+    natural: &[usize],
+    minimum: &[usize],
+    available: usize,
+) -> Vec<usize> {
+    if natural.iter().sum::<usize>() <= available {
+        return natural.to_vec();
+    }
+    let spare = available - minimum.iter().sum::<usize>();
+    let wanted: usize = natural.iter().zip(minimum).map(|(n, m)| n - m).sum();
+    let mut widths: Vec<usize> = natural
+        .iter()
+        .zip(minimum)
+        .map(|(n, m)| m + spare * (n - m) / wanted.max(1))
+        .collect();
+    let mut left = available - widths.iter().sum::<usize>();
+    for (width, natural) in widths.iter_mut().zip(natural) {
+        let extra = left.min(natural - *width);
+        *width += extra;
+        left -= extra;
+    }
+    widths
+}
+
+fn bordered(rows: Vec<Vec<Cell>>, widths: &[usize]) -> Vec<Line<'static>> {
+    let rule = |left: &str, middle: &str, right: &str| {
+        let segments: Vec<String> =
+            widths.iter().map(|width| "─".repeat(width + 2)).collect();
+        Line::from(format!("{}{}{}", left, segments.join(middle), right)).dim()
+    };
+    let is_header =
+        |row: &[Cell]| !row.is_empty() && row.iter().all(|cell| cell.header);
+    let wrapped: Vec<(bool, Vec<Vec<Line<'static>>>)> = rows
+        .iter()
+        .map(|row| {
+            let cells = widths
+                .iter()
+                .enumerate()
+                .map(|(column, &width)| match row.get(column) {
+                    Some(cell) => cell
+                        .lines
+                        .iter()
+                        .flat_map(|line| wrap_line(line, width))
+                        .collect(),
+                    None => vec![],
+                })
+                .collect();
+            (is_header(row), cells)
+        })
+        .collect();
+    // Rows are only ruled off from each other when some take up more than
+    // one line
+    let separate_rows = wrapped
+        .iter()
+        .any(|(_, cells)| cells.iter().any(|lines| lines.len() > 1));
+    let mut lines = vec![rule("┌", "┬", "┐")];
+    for (i, (header, cells)) in wrapped.iter().enumerate() {
+        let row = &rows[i];
+        let height = cells.iter().map(Vec::len).max().unwrap_or(0).max(1);
+        for line_index in 0..height {
+            let mut spans = vec![Span::raw("│ ").dim()];
+            for (column, &width) in widths.iter().enumerate() {
+                if column > 0 {
+                    spans.push(Span::raw(" │ ").dim());
+                }
+                let line = cells[column].get(line_index);
+                let align =
+                    row.get(column).map_or(Alignment::Left, |c| c.align);
+                spans.extend(pad(line, width, align, *header));
+            }
+            spans.push(Span::raw(" │").dim());
+            lines.push(Line::from(spans));
+        }
+        if let Some((next_header, _)) = wrapped.get(i + 1)
+            && ((*header && !next_header) || separate_rows)
+        {
+            lines.push(rule("├", "┼", "┤"));
+        }
+    }
+    lines.push(rule("└", "┴", "┘"));
+    lines
+}
+
+/// The spans of a line of a cell padded with spaces to `width`.
+fn pad(
+    line: Option<&Line<'static>>,
+    width: usize,
+    align: Alignment,
+    header: bool,
+) -> Vec<Span<'static>> {
+    let mut spans = line.map_or(vec![], |line| line.spans.clone());
+    if header {
+        spans = spans.into_iter().map(|span| span.bold()).collect();
+    }
+    let used: usize = spans.iter().map(Span::width).sum();
+    let spare = width.saturating_sub(used);
+    let before = match align {
+        Alignment::Left => 0,
+        Alignment::Center => spare / 2,
+        Alignment::Right => spare,
+    };
+    spans.insert(0, Span::raw(" ".repeat(before)));
+    spans.push(Span::raw(" ".repeat(spare - before)));
+    spans
+}
+
+/// Each row as its cells one after the other, each labelled with the
+/// header of its column, for tables too wide for the pane.
+fn stacked(rows: Vec<Vec<Cell>>, width: usize) -> Vec<Line<'static>> {
+    let header_count = rows
+        .iter()
+        .take_while(|row| row.iter().all(|cell| cell.header))
+        .count();
+    let labels: Vec<String> = match rows.first() {
+        Some(row) if header_count > 0 => row
+            .iter()
+            .map(|cell| {
+                let text: Vec<String> =
+                    cell.lines.iter().map(line_text).collect();
+                text.join(" ")
+            })
+            .collect(),
+        _ => vec![],
+    };
+    let mut lines = vec![];
+    for row in rows.into_iter().skip(header_count) {
+        lines.push(Line::from("─".repeat(width.clamp(1, 20))).dim());
+        for (column, cell) in row.into_iter().enumerate() {
+            let mut cell_lines = cell.lines.into_iter();
+            let Some(first) = cell_lines.next() else {
+                continue;
+            };
+            let mut first_spans = vec![];
+            if let Some(label) = labels.get(column).filter(|l| !l.is_empty()) {
+                first_spans.push(Span::raw(format!("{}: ", label)).bold());
+            }
+            first_spans.extend(first.spans);
+            lines.push(Line::from(first_spans));
+            lines.extend(cell_lines);
+        }
+    }
+    lines
+}
+
+fn line_text(line: &Line) -> String {
+    line.spans.iter().map(|s| s.content.as_ref()).collect()
+}
+
+/// The cell without blank lines, or whitespace at either end of its lines.
+fn trim_cell(mut cell: Cell) -> Cell {
+    cell.lines = cell
+        .lines
+        .into_iter()
+        .map(trim_line)
+        .filter(|line| line.width() > 0)
+        .collect();
+    cell
+}
+
+fn trim_line(mut line: Line<'static>) -> Line<'static> {
+    while line
+        .spans
+        .first()
+        .is_some_and(|s| s.content.trim().is_empty())
+    {
+        line.spans.remove(0);
+    }
+    while line
+        .spans
+        .last()
+        .is_some_and(|s| s.content.trim().is_empty())
+    {
+        line.spans.pop();
+    }
+    if let Some(first) = line.spans.first_mut() {
+        first.content = first.content.trim_start().to_string().into();
+    }
+    if let Some(last) = line.spans.last_mut() {
+        last.content = last.content.trim_end().to_string().into();
+    }
+    line
+}
+
+/// Wrap a line to `width` columns at spaces, breaking words longer than a
+/// line.
+pub fn wrap_line(line: &Line<'static>, width: usize) -> Vec<Line<'static>> {
+    let width = width.max(1);
+    let mut lines = vec![];
+    let mut current: Vec<Span<'static>> = vec![];
+    let mut current_width = 0;
+    for word in words(line) {
+        let word_width: usize = word.iter().map(Span::width).sum();
+        let trimmed_width = trim_line(Line::from(word.clone())).width();
+        if current_width > 0 && current_width + trimmed_width > width {
+            lines.push(trim_line(Line::from(std::mem::take(&mut current))));
+            current_width = 0;
+        }
+        if trimmed_width <= width {
+            current.extend(word);
+            current_width += word_width;
+            continue;
+        }
+        for span in word {
+            for c in span.content.chars() {
+                let char_width = c.width().unwrap_or(0);
+                if current_width > 0 && current_width + char_width > width {
+                    lines.push(Line::from(std::mem::take(&mut current)));
+                    current_width = 0;
+                }
+                match current.last_mut() {
+                    Some(last) if last.style == span.style => {
+                        last.content.to_mut().push(c);
+                    }
+                    _ => current.push(Span::styled(c.to_string(), span.style)),
+                }
+                current_width += char_width;
+            }
+        }
+    }
+    if !current.is_empty() {
+        lines.push(trim_line(Line::from(current)));
+    }
+    lines
+}
+
+/// The words of a line, each with the spaces after it. Words can be made of
+/// several spans, such as a link and its reference.
+fn words(line: &Line<'static>) -> Vec<Vec<Span<'static>>> {
+    let mut words = vec![vec![]];
+    for span in &line.spans {
+        for piece in span.content.split_inclusive(' ') {
+            if let Some(word) = words.last_mut() {
+                word.push(Span::styled(piece.to_string(), span.style));
+            }
+            if piece.ends_with(' ') {
+                words.push(vec![]);
+            }
+        }
+    }
+    words.retain(|word| !word.is_empty());
+    words
+}